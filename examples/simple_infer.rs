@@ -17,8 +17,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Inferring...");
     let result = asr.infer_file(audio_path)?;
 
-    for vt in result {
-        println!("Content: {}", vt);
+    println!("Content: {}", result);
+    for segment in &result.segments {
+        println!("[{:.2}s - {:.2}s] {}", segment.start, segment.end, segment.text);
     }
 
     Ok(())
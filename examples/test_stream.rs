@@ -4,8 +4,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     use breeze_asr_rs::{BreezeASR, VadConfig};
     use futures::stream::StreamExt;
     use async_stream::stream;
-    use std::io::Read;
-    use std::time::Duration;
     use hound;
     
     let args: Vec<String> = std::env::args().collect();
@@ -23,7 +21,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     println!("Initializing BreezeASR with VAD...");
-    let mut asr = BreezeASR::init_with_vad(None, VadConfig::default())?;
+    let asr = BreezeASR::builder().vad_config(VadConfig::default()).build()?;
 
     println!("Starting stream inference...");
     
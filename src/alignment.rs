@@ -0,0 +1,130 @@
+use ndarray::{Array2, Array3, Axis};
+
+use crate::transcript::TIME_PRECISION;
+
+const MEDIAN_FILTER_WIDTH: usize = 7;
+
+/// Token boundaries from decoder cross-attention, as in Whisper's `find_alignment`.
+///
+/// `qk` holds raw (pre-softmax) cross-attention scores of shape `[heads, positions, frames]`,
+/// where position `k` is the one predicting text token `k` (so the first position is
+/// `<|notimestamps|>` and the last one is the last text token). Only the first
+/// `num_frames` audio frames are used.
+///
+/// Returns `positions` times in seconds: text token `k` spans `[times[k], times[k + 1]]`,
+/// the last token ends at the last returned time.
+pub fn token_boundaries(qk: &Array3<f32>, num_frames: usize) -> Vec<f32> {
+    let num_frames = num_frames.clamp(1, qk.shape()[2]);
+    let mut weights = qk.slice(ndarray::s![.., .., ..num_frames]).to_owned();
+
+    // Softmax over audio frames
+    for mut row in weights.lanes_mut(Axis(2)) {
+        let max = row.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        row.mapv_inplace(|x| (x - max).exp());
+        let sum = row.sum();
+        row.mapv_inplace(|x| x / sum);
+    }
+
+    // Standardize over tokens, then smooth over time
+    for mut column in weights.lanes_mut(Axis(1)) {
+        let n = column.len() as f32;
+        let mean = column.sum() / n;
+        let std = (column.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n).sqrt().max(1e-10);
+        column.mapv_inplace(|x| (x - mean) / std);
+    }
+    for mut row in weights.lanes_mut(Axis(2)) {
+        let filtered = median_filter(&row.to_vec(), MEDIAN_FILTER_WIDTH);
+        row.assign(&ndarray::ArrayView1::from(&filtered));
+    }
+
+    let matrix = weights.mean_axis(Axis(0)).unwrap();
+    let path = dtw(&matrix.mapv(|x| -x));
+
+    // A new token starts wherever the path moves down a row
+    let mut times = Vec::with_capacity(matrix.nrows());
+    let mut last_row = None;
+    for (row, frame) in path {
+        if last_row != Some(row) {
+            times.push(frame as f32 * TIME_PRECISION);
+            last_row = Some(row);
+        }
+    }
+    times
+}
+
+/// Dynamic time warping over a cost matrix of shape `[tokens, frames]`.
+/// Returns the monotonic path as `(token, frame)` pairs.
+pub fn dtw(cost: &Array2<f32>) -> Vec<(usize, usize)> {
+    let (n, m) = cost.dim();
+    let mut acc = Array2::<f32>::from_elem((n + 1, m + 1), f32::INFINITY);
+    let mut trace = Array2::<u8>::from_elem((n + 1, m + 1), 2);
+    acc[[0, 0]] = 0.0;
+
+    for j in 1..=m {
+        for i in 1..=n {
+            let c0 = acc[[i - 1, j - 1]];
+            let c1 = acc[[i - 1, j]];
+            let c2 = acc[[i, j - 1]];
+            let (c, t) = if c0 < c1 && c0 < c2 {
+                (c0, 0)
+            } else if c1 < c0 && c1 < c2 {
+                (c1, 1)
+            } else {
+                (c2, 2)
+            };
+            acc[[i, j]] = cost[[i - 1, j - 1]] + c;
+            trace[[i, j]] = t;
+        }
+    }
+    for j in 0..=m {
+        trace[[0, j]] = 2;
+    }
+    for i in 0..=n {
+        trace[[i, 0]] = 1;
+    }
+
+    let (mut i, mut j) = (n, m);
+    let mut path = Vec::with_capacity(n + m);
+    while i > 0 || j > 0 {
+        path.push((i.saturating_sub(1), j.saturating_sub(1)));
+        match trace[[i, j]] {
+            0 => {
+                i -= 1;
+                j -= 1;
+            }
+            1 => i -= 1,
+            _ => j -= 1,
+        }
+    }
+    path.reverse();
+    path
+}
+
+/// Median of each `width` wide window, padding both ends by reflection like SciPy.
+pub fn median_filter(input: &[f32], width: usize) -> Vec<f32> {
+    let half = width / 2;
+    if input.len() <= half {
+        return input.to_vec();
+    }
+
+    let mut window = Vec::with_capacity(width);
+    (0..input.len())
+        .map(|i| {
+            // Reflect padding at both ends
+            window.clear();
+            for k in 0..width {
+                let idx = i as isize + k as isize - half as isize;
+                let idx = if idx < 0 {
+                    (-idx) as usize
+                } else if idx as usize >= input.len() {
+                    2 * (input.len() - 1) - idx as usize
+                } else {
+                    idx as usize
+                };
+                window.push(input[idx.min(input.len() - 1)]);
+            }
+            window.sort_by(|a, b| a.total_cmp(b));
+            window[half]
+        })
+        .collect()
+}
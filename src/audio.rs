@@ -1,57 +1,126 @@
 use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use ndarray::{Array1, Array2};
 use rustfft::{FftPlanner, num_complex::Complex};
 use rubato::{Resampler, SincFixedIn, SincInterpolationType, SincInterpolationParameters, WindowFunction};
-use hound::WavReader;
-use anyhow::{Result, Context};
+use hound::{SampleFormat, WavReader};
+
+use crate::error::{BreezeError, Result};
 
 // Whisper parameters
-const SAMPLE_RATE: usize = 16000;
+pub const SAMPLE_RATE: usize = 16000;
 const N_FFT: usize = 400;
 const HOP_LENGTH: usize = 160;
 const CHUNK_LENGTH: usize = 30;
-const N_SAMPLES: usize = CHUNK_LENGTH * SAMPLE_RATE;
-const N_MELS: usize = 80;
+pub const N_SAMPLES: usize = CHUNK_LENGTH * SAMPLE_RATE;
+
+// Long-form windowing: look for a quiet spot in the last few seconds of a window
+const CUT_SEARCH_SAMPLES: usize = 5 * SAMPLE_RATE;
+const CUT_FRAME_SAMPLES: usize = 2 * HOP_LENGTH;
+
+/// Shape of the log-mel spectrogram fed to the encoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    /// Number of mel bins: 80, or 128 for large-v3 based models.
+    pub n_mels: usize,
+    /// Highest frequency of the mel filters, in Hz.
+    pub fmax: f32,
+    /// Frames per 30s window; the spectrogram is padded or cut to this length.
+    pub n_frames: usize,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            n_mels: 80,
+            fmax: 8000.0,
+            n_frames: 3000,
+        }
+    }
+}
+
+/// How the channels of a multi-channel file are turned into mono audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelPolicy {
+    /// Average all channels into one.
+    #[default]
+    Average,
+    /// Use only the channel at this index, e.g. 0 for the left channel.
+    Select(usize),
+    /// Keep every channel, to transcribe each on its own.
+    Separate,
+}
 
 pub struct AudioProcessor {
+    config: AudioConfig,
     mel_filters: Array2<f32>,
 }
 
 impl AudioProcessor {
+    /// Feature extractor for 80-bin models like Breeze ASR 25.
     pub fn new() -> Result<Self> {
+        Self::with_config(AudioConfig::default())
+    }
+
+    pub fn with_config(config: AudioConfig) -> Result<Self> {
+        if config.n_mels == 0 || config.n_frames == 0 {
+            return Err(BreezeError::InvalidConfig(format!("invalid audio config: {:?}", config)));
+        }
+        if config.fmax <= 0.0 || config.fmax > SAMPLE_RATE as f32 / 2.0 {
+            return Err(BreezeError::InvalidConfig(format!(
+                "fmax must be in (0, {}] Hz, got {}",
+                SAMPLE_RATE / 2, config.fmax
+            )));
+        }
+
         // Generate Mel filters
         let mel_filters = mel_filter_bank(
             SAMPLE_RATE as f32,
             N_FFT as f32,
-            N_MELS,
+            config.n_mels,
             0.0,
-            8000.0,
+            config.fmax,
         );
-        Ok(Self { mel_filters })
+        Ok(Self { config, mel_filters })
     }
 
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    /// Load a file and compute the mel spectrogram of its first 30 seconds.
+    /// Use `load_audio` + `process_pcm` per window for longer files.
     pub fn load_and_preprocess(&self, path: &str) -> Result<Array2<f32>> {
-        println!("Loading audio from: {}", path);
-        let (samples, sr) = read_wav(path)?;
-        // Just call process_pcm with the samples and their original sample rate
-        // We do the resampling here if needed because process_pcm expects input ready for log_mel_spectrogram?
-        // Wait, log_mel_spectrogram expects 16kHz.
-        // So I should do resampling in process_pcm or before?
-        // load_and_preprocess used to do resampling before log_mel_spectrogram.
-        // I will make process_pcm take &[f32] which ARE already 16kHz, OR make it take SR?
-        // To be flexible for streaming (which is usually 16kHz), let's assume process_pcm takes 16kHz.
-        // But better to be explicit.
-        
-        let resampled = if sr != SAMPLE_RATE {
-            resample_audio(&samples, sr, SAMPLE_RATE)?
-        } else {
-            samples
-        };
-        
-        Ok(self.process_pcm(&resampled))
+        let samples = self.load_audio(path)?;
+        Ok(self.process_pcm(&samples))
+    }
+
+    /// Load a file as 16kHz PCM samples, resampling if needed.
+    /// Multi-channel files are averaged into mono.
+    /// Besides WAV, the `formats` feature reads FLAC, MP3, Ogg Vorbis and MP4/M4A (AAC),
+    /// and the `opus` feature Ogg Opus.
+    pub fn load_audio(&self, path: &str) -> Result<Vec<f32>> {
+        let mut channels = self.load_channels(path, ChannelPolicy::Average)?;
+        Ok(channels.remove(0))
+    }
+
+    /// Load a file as 16kHz PCM samples, one `Vec` per channel kept by `policy`:
+    /// a single one for `Average` and `Select`, every channel for `Separate`.
+    pub fn load_channels(&self, path: &str, policy: ChannelPolicy) -> Result<Vec<Vec<f32>>> {
+        let (channels, sr) = read_audio(path)?;
+        apply_channel_policy(channels, sr, policy)
+    }
+
+    /// Same as `load_audio`, for a whole audio file in memory or received over the network.
+    pub fn load_reader(&self, reader: impl Read + Seek) -> Result<Vec<f32>> {
+        let (channels, sr) = read_audio_from(reader)?;
+        let mut channels = apply_channel_policy(channels, sr, ChannelPolicy::Average)?;
+        Ok(channels.remove(0))
     }
 
     /// Process PCM audio samples (must be 16kHz).
+    /// Only the first 30 seconds are used; longer audio must be split into windows.
     pub fn process_pcm(&self, samples: &[f32]) -> Array2<f32> {
         self.log_mel_spectrogram(samples)
     }
@@ -85,12 +154,12 @@ impl AudioProcessor {
         let max_val = log_spec.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
         log_spec.mapv_inplace(|x| (x.max(max_val - 8.0) + 4.0) / 4.0);
 
-        // Ensure exactly 3000 frames
+        // Ensure exactly `n_frames` frames
         let current_frames = log_spec.shape()[0];
-        let target_frames = 3000;
+        let target_frames = self.config.n_frames;
         
         let final_spec = if current_frames < target_frames {
-            let mut padded = Array2::zeros((target_frames, N_MELS));
+            let mut padded = Array2::zeros((target_frames, self.config.n_mels));
             padded.slice_mut(ndarray::s![..current_frames, ..]).assign(&log_spec);
             padded
         } else if current_frames > target_frames {
@@ -99,22 +168,154 @@ impl AudioProcessor {
             log_spec
         };
 
-        // Transpose to [Mel, Time] -> [80, 3000]
+        // Transpose to [Mel, Time] -> [n_mels, n_frames]
         final_spec.t().to_owned()
     }
 }
 
-fn read_wav(path: &str) -> Result<(Vec<f32>, usize)> {
-    let mut reader = WavReader::open(path).with_context(|| format!("Failed to open wav file '{}'", path))?;
+/// Find where the 30s window starting at `start` should end.
+///
+/// If the rest of the audio fits in one window, this is the end of the audio.
+/// Otherwise the window is cut at the quietest frame in its last few seconds,
+/// so that words are less likely to be split across two windows.
+pub fn next_window_end(samples: &[f32], start: usize) -> usize {
+    let limit = start + N_SAMPLES;
+    if limit >= samples.len() {
+        return samples.len();
+    }
+
+    let search_start = limit - CUT_SEARCH_SAMPLES;
+    let (best, _) = samples[search_start..limit]
+        .chunks_exact(CUT_FRAME_SAMPLES)
+        .enumerate()
+        .map(|(i, frame)| (i, frame.iter().map(|x| x * x).sum::<f32>()))
+        .fold((0, f32::INFINITY), |(argmin, min), (i, energy)| {
+            if energy <= min { (i, energy) } else { (argmin, min) }
+        });
+
+    search_start + best * CUT_FRAME_SAMPLES + CUT_FRAME_SAMPLES / 2
+}
+
+/// Decode an audio file into its channels and sample rate, picking the decoder from
+/// its content. WAV is always supported; other formats need the `formats` feature.
+fn read_audio(path: &str) -> Result<(Vec<Vec<f32>>, usize)> {
+    let mut file = File::open(path)
+        .map_err(|e| BreezeError::Io(std::io::Error::new(e.kind(), format!("failed to open '{}': {}", path, e))))?;
+    if is_wav(&mut file)? {
+        return read_wav(file);
+    }
+
+    #[cfg(feature = "formats")]
+    return crate::formats::decode(file);
+    #[cfg(not(feature = "formats"))]
+    Err(BreezeError::UnsupportedAudioFormat(
+        "only WAV can be read without the `formats` feature".to_string(),
+    ))
+}
+
+/// Same as `read_audio`, for any reader. Formats other than WAV are read into memory first.
+fn read_audio_from(mut reader: impl Read + Seek) -> Result<(Vec<Vec<f32>>, usize)> {
+    if is_wav(&mut reader)? {
+        return read_wav(reader);
+    }
+
+    #[cfg(feature = "formats")]
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        crate::formats::decode(std::io::Cursor::new(bytes))
+    }
+    #[cfg(not(feature = "formats"))]
+    Err(BreezeError::UnsupportedAudioFormat(
+        "only WAV can be read without the `formats` feature".to_string(),
+    ))
+}
+
+/// Whether the content is a RIFF WAVE file. The reader is rewound to where it was.
+fn is_wav(reader: &mut (impl Read + Seek)) -> Result<bool> {
+    let start = reader.stream_position()?;
+    let mut header = Vec::with_capacity(12);
+    reader.take(12).read_to_end(&mut header)?;
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WAVE"))
+}
+
+/// Read a WAV file as samples in [-1, 1]. Integer samples of 8 to 32 bits and
+/// 32-bit float samples are supported.
+fn read_wav(reader: impl Read) -> Result<(Vec<Vec<f32>>, usize)> {
+    let mut reader = WavReader::new(BufReader::new(reader))?;
     let spec = reader.spec();
-    let samples: Vec<f32> = reader
-        .samples::<i16>()
-        .map(|s| s.map(|x| x as f32 / 32768.0))
-        .collect::<Result<Vec<f32>, _>>()?;
-    Ok((samples, spec.sample_rate as usize))
+    let samples: Vec<f32> = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => reader.samples::<f32>().collect::<std::result::Result<_, _>>()?,
+        (SampleFormat::Int, bits @ 1..=32) => {
+            // hound gives 8-bit (unsigned) samples as signed values, like the other widths
+            let scale = (1u64 << (bits - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|x| x as f32 / scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+        (format, bits) => {
+            return Err(BreezeError::UnsupportedAudioFormat(format!(
+                "{}-bit {:?} WAV samples",
+                bits, format
+            )))
+        }
+    };
+    Ok((deinterleave(&samples, spec.channels as usize), spec.sample_rate as usize))
 }
 
-fn resample_audio(samples: &[f32], from_sr: usize, to_sr: usize) -> Result<Vec<f32>> {
+/// Keep the channels selected by `policy`, at 16kHz.
+fn apply_channel_policy(channels: Vec<Vec<f32>>, sr: usize, policy: ChannelPolicy) -> Result<Vec<Vec<f32>>> {
+    let channels = match policy {
+        ChannelPolicy::Average => vec![downmix(&channels)],
+        ChannelPolicy::Select(index) => {
+            let count = channels.len();
+            let channel = channels.into_iter().nth(index).ok_or_else(|| {
+                BreezeError::InvalidConfig(format!("channel {} selected, but the audio has {}", index, count))
+            })?;
+            vec![channel]
+        }
+        ChannelPolicy::Separate => channels,
+    };
+
+    if sr != SAMPLE_RATE {
+        channels.iter().map(|samples| resample_audio(samples, sr, SAMPLE_RATE)).collect()
+    } else {
+        Ok(channels)
+    }
+}
+
+/// Split interleaved frames into one `Vec` per channel.
+pub(crate) fn deinterleave(samples: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    let channels = channels.max(1);
+    (0..channels)
+        .map(|c| samples.iter().skip(c).step_by(channels).copied().collect())
+        .collect()
+}
+
+/// Average channels into mono.
+fn downmix(channels: &[Vec<f32>]) -> Vec<f32> {
+    match channels {
+        [] => Vec::new(),
+        [mono] => mono.clone(),
+        _ => {
+            let len = channels.iter().map(Vec::len).min().unwrap_or(0);
+            (0..len)
+                .map(|i| channels.iter().map(|c| c[i]).sum::<f32>() / channels.len() as f32)
+                .collect()
+        }
+    }
+}
+
+/// Resample mono audio from `from_sr` to `to_sr` Hz, e.g. to `SAMPLE_RATE` for the model.
+pub fn resample_audio(samples: &[f32], from_sr: usize, to_sr: usize) -> Result<Vec<f32>> {
+    if from_sr == to_sr || samples.is_empty() {
+        return Ok(samples.to_vec());
+    }
+    if from_sr == 0 || to_sr == 0 {
+        return Err(BreezeError::Resample(format!("cannot resample from {} Hz to {} Hz", from_sr, to_sr)));
+    }
     let params = SincInterpolationParameters {
         sinc_len: 256,
         f_cutoff: 0.95,
@@ -124,17 +325,34 @@ fn resample_audio(samples: &[f32], from_sr: usize, to_sr: usize) -> Result<Vec<f
     };
     
     let ratio = to_sr as f64 / from_sr as f64;
+    // The ratio is fixed, so it never needs to change relative to the initial one
     let mut resampler = SincFixedIn::<f32>::new(
         ratio,
-        ratio, 
+        1.0,
         params,
         samples.len(),
         1,
-    )?;
+    )
+    .map_err(|e| BreezeError::Resample(e.to_string()))?;
 
     let waves_in = vec![samples.to_vec()];
-    let waves_out = resampler.process(&waves_in, None)?;
-    Ok(waves_out[0].clone())
+    let mut output = resampler
+        .process(&waves_in, None)
+        .map_err(|e| BreezeError::Resample(e.to_string()))?
+        .remove(0);
+
+    // The filter delays the output; flush it so the end of the audio is not lost
+    let delay = resampler.output_delay();
+    let expected = (samples.len() as f64 * ratio).round() as usize;
+    while output.len() < delay + expected {
+        let tail = resampler
+            .process_partial::<Vec<f32>>(None, None)
+            .map_err(|e| BreezeError::Resample(e.to_string()))?;
+        output.extend_from_slice(&tail[0]);
+    }
+    output.drain(..delay);
+    output.truncate(expected);
+    Ok(output)
 }
 
 fn hann_window(size: usize) -> Array1<f32> {
@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+use hf_hub::api::sync::ApiBuilder;
+
+use crate::audio::{AudioConfig, AudioProcessor};
+use crate::error::{BreezeError, Result};
+use crate::model::{BreezeModel, DecodingOptions};
+use crate::session::SessionOptions;
+use crate::tokenizer::Tokenizer;
+use crate::BreezeASR;
+
+#[cfg(feature = "stream")]
+use crate::vad::{VadConfig, VadProcessor};
+#[cfg(feature = "stream")]
+use std::sync::Mutex;
+
+const DEFAULT_REPO: &str = "MediaTek-Research/Breeze-ASR-25-onnx-250806";
+const ENCODER_FILE: &str = "breeze-asr-25-encoder.onnx";
+const DECODER_FILE: &str = "breeze-asr-25-decoder.onnx";
+const TOKENS_FILE: &str = "breeze-asr-25-tokens.txt";
+/// External weights of the ONNX files, which must sit next to them.
+const WEIGHT_FILES: [&str; 2] = ["breeze-asr-25-encoder.weights", "breeze-asr-25-decoder.weights"];
+
+/// Where the model files come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelSource {
+    /// Download a repo with the Breeze file names from Hugging Face.
+    /// `cache_dir` defaults to the Hugging Face cache.
+    HuggingFace { repo: String, cache_dir: Option<PathBuf> },
+    /// A local directory with the Breeze file names.
+    Dir(PathBuf),
+    /// Explicit paths, e.g. for other Whisper exports.
+    Files { encoder: PathBuf, decoder: PathBuf, tokens: PathBuf },
+}
+
+impl Default for ModelSource {
+    fn default() -> Self {
+        Self::HuggingFace { repo: DEFAULT_REPO.to_string(), cache_dir: None }
+    }
+}
+
+impl ModelSource {
+    /// Local paths of the encoder, decoder and tokens files, downloading them if needed.
+    fn resolve(&self) -> Result<(PathBuf, PathBuf, PathBuf)> {
+        match self {
+            Self::HuggingFace { repo, cache_dir } => {
+                let mut api = ApiBuilder::new();
+                if let Some(dir) = cache_dir {
+                    api = api.with_cache_dir(dir.clone());
+                }
+                let repo = api.build()?.model(repo.clone());
+                for file in WEIGHT_FILES {
+                    repo.get(file)?;
+                }
+                Ok((repo.get(ENCODER_FILE)?, repo.get(DECODER_FILE)?, repo.get(TOKENS_FILE)?))
+            }
+            Self::Dir(dir) => Ok((dir.join(ENCODER_FILE), dir.join(DECODER_FILE), dir.join(TOKENS_FILE))),
+            Self::Files { encoder, decoder, tokens } => Ok((encoder.clone(), decoder.clone(), tokens.clone())),
+        }
+    }
+}
+
+/// Collects the settings of a `BreezeASR` and loads it.
+///
+/// ```no_run
+/// use breeze_asr_rs::{BreezeASR, DecodingOptions, SessionOptions};
+///
+/// let asr = BreezeASR::builder()
+///     .model_dir("/path/to/models")
+///     .session_options(SessionOptions { intra_threads: Some(2), ..Default::default() })
+///     .decoding_options(DecodingOptions { language: Some("zh".to_string()), ..Default::default() })
+///     .build()?;
+/// # Ok::<(), breeze_asr_rs::BreezeError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct BreezeASRBuilder {
+    source: ModelSource,
+    session_options: SessionOptions,
+    decoding_options: DecodingOptions,
+    audio_config: Option<AudioConfig>,
+    batch_size: usize,
+    #[cfg(feature = "stream")]
+    vad_config: VadConfig,
+}
+
+impl Default for BreezeASRBuilder {
+    fn default() -> Self {
+        Self {
+            source: ModelSource::default(),
+            session_options: SessionOptions::default(),
+            decoding_options: DecodingOptions::default(),
+            audio_config: None,
+            batch_size: 8,
+            #[cfg(feature = "stream")]
+            vad_config: VadConfig::default(),
+        }
+    }
+}
+
+impl BreezeASRBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn source(mut self, source: ModelSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Load the Breeze files from a local directory.
+    pub fn model_dir(self, dir: impl Into<PathBuf>) -> Self {
+        self.source(ModelSource::Dir(dir.into()))
+    }
+
+    /// Download the Breeze files from another Hugging Face repo.
+    pub fn hf_repo(self, repo: impl Into<String>) -> Self {
+        self.source(ModelSource::HuggingFace { repo: repo.into(), cache_dir: None })
+    }
+
+    pub fn session_options(mut self, options: SessionOptions) -> Self {
+        self.session_options = options;
+        self
+    }
+
+    /// Options used by `infer_file` and the stream APIs.
+    pub fn decoding_options(mut self, options: DecodingOptions) -> Self {
+        self.decoding_options = options;
+        self
+    }
+
+    /// Override the feature extractor settings, which otherwise follow the encoder input.
+    pub fn audio_config(mut self, config: AudioConfig) -> Self {
+        self.audio_config = Some(config);
+        self
+    }
+
+    /// Number of short clips `infer_files` encodes and decodes at once.
+    /// Memory grows with it, by about 0.5GB per clip for large models.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    #[cfg(feature = "stream")]
+    pub fn vad_config(mut self, config: VadConfig) -> Self {
+        self.vad_config = config;
+        self
+    }
+
+    /// Check the settings, then load the model.
+    pub fn build(self) -> Result<BreezeASR> {
+        self.decoding_options.validate()?;
+        self.session_options.validate()?;
+        if self.batch_size == 0 {
+            return Err(BreezeError::InvalidConfig("batch size must be at least 1".to_string()));
+        }
+        #[cfg(feature = "stream")]
+        validate_vad_config(&self.vad_config)?;
+
+        let (encoder_path, decoder_path, tokenizer_path) = self.source.resolve()?;
+        let path_str = |path: &PathBuf| {
+            path.to_str()
+                .map(str::to_string)
+                .ok_or_else(|| BreezeError::InvalidConfig(format!("invalid model path '{}'", path.display())))
+        };
+
+        let model = BreezeModel::with_options(&path_str(&encoder_path)?, &path_str(&decoder_path)?, &self.session_options)?;
+        let dims = model.dims();
+
+        if let Some(language) = &self.decoding_options.language {
+            if dims.tokens.multilingual {
+                dims.tokens.language_token(language)?;
+            }
+        }
+
+        // Match the spectrogram to what the encoder takes, e.g. 128 bins for large-v3 models
+        let audio_config = self.audio_config.unwrap_or(AudioConfig {
+            n_mels: dims.n_mels,
+            n_frames: dims.n_frames,
+            ..AudioConfig::default()
+        });
+        if audio_config.n_mels != dims.n_mels || audio_config.n_frames != dims.n_frames {
+            return Err(BreezeError::InvalidConfig(format!(
+                "audio config has {} mel bins and {} frames, but the encoder takes {} and {}",
+                audio_config.n_mels, audio_config.n_frames, dims.n_mels, dims.n_frames
+            )));
+        }
+        let audio_processor = AudioProcessor::with_config(audio_config)?;
+
+        let tokenizer = Tokenizer::new(&path_str(&tokenizer_path)?)?;
+
+        Ok(BreezeASR {
+            model,
+            tokenizer,
+            audio_processor,
+            options: self.decoding_options,
+            batch_size: self.batch_size,
+            #[cfg(feature = "stream")]
+            vad_processor: Mutex::new(Some(VadProcessor::new(self.vad_config)?)),
+            #[cfg(feature = "stream")]
+            vad_config: self.vad_config,
+        })
+    }
+}
+
+#[cfg(feature = "stream")]
+fn validate_vad_config(config: &VadConfig) -> Result<()> {
+    // Speech segments go to the model as they are, without resampling
+    if config.sample_rate as usize != crate::audio::SAMPLE_RATE {
+        return Err(BreezeError::InvalidConfig(format!(
+            "VAD sample rate must be {} Hz, got {}",
+            crate::audio::SAMPLE_RATE, config.sample_rate
+        )));
+    }
+    if !(0.0..=1.0).contains(&config.speech_threshold) {
+        return Err(BreezeError::InvalidConfig(format!(
+            "VAD speech threshold must be in [0, 1], got {}",
+            config.speech_threshold
+        )));
+    }
+    if config.max_speech_duration_ms == 0 {
+        return Err(BreezeError::InvalidConfig("VAD max speech duration must not be 0".to_string()));
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors returned by this crate.
+#[derive(Debug, Error)]
+pub enum BreezeError {
+    /// A model or tokenizer file does not exist.
+    #[error("model file not found: {}", .0.display())]
+    ModelNotFound(PathBuf),
+
+    /// ONNX Runtime could not create a session for a model file.
+    #[error("failed to load model '{}': {source}", path.display())]
+    ModelLoad {
+        path: PathBuf,
+        #[source]
+        source: ort::Error,
+    },
+
+    /// The model does not look like a Whisper export, e.g. an expected output is missing.
+    #[error("unsupported model: {0}")]
+    UnsupportedModel(String),
+
+    /// Downloading from Hugging Face failed.
+    #[error("download failed: {0}")]
+    Download(#[from] hf_hub::api::sync::ApiError),
+
+    /// The audio could not be decoded.
+    #[error("failed to decode audio: {0}")]
+    AudioDecode(String),
+
+    /// The audio is valid, but its encoding is not supported.
+    #[error("unsupported audio format: {0}")]
+    UnsupportedAudioFormat(String),
+
+    #[error("resampling failed: {0}")]
+    Resample(String),
+
+    /// A line of the tokens file could not be parsed.
+    #[error("invalid tokenizer file at line {line}: {message}")]
+    TokenizerParse { line: usize, message: String },
+
+    /// The encoder failed.
+    #[error("encoder failed: {0}")]
+    Encoder(#[source] ort::Error),
+
+    /// The decoder failed while decoding the token at `step`.
+    #[error("decoder failed at step {step}: {source}")]
+    Decoder {
+        step: usize,
+        #[source]
+        source: ort::Error,
+    },
+
+    /// Any other ONNX Runtime failure, e.g. creating or reading a tensor.
+    #[error("inference failed: {0}")]
+    Inference(#[from] ort::Error),
+
+    #[error("unsupported language: {0}")]
+    UnsupportedLanguage(String),
+
+    /// An option is out of range or does not fit the model.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// The voice activity detector failed.
+    #[error("streaming failed: {0}")]
+    Stream(String),
+
+    /// A thread panicked while holding the named resource.
+    #[error("{0} is poisoned by a panic in another thread")]
+    Poisoned(&'static str),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, BreezeError>;
+
+impl From<ndarray::ShapeError> for BreezeError {
+    fn from(e: ndarray::ShapeError) -> Self {
+        Self::UnsupportedModel(format!("unexpected tensor shape: {}", e))
+    }
+}
+
+impl From<hound::Error> for BreezeError {
+    fn from(e: hound::Error) -> Self {
+        match e {
+            hound::Error::IoError(e) => Self::Io(e),
+            hound::Error::Unsupported => Self::UnsupportedAudioFormat("WAV encoding".to_string()),
+            e => Self::AudioDecode(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::error::{BreezeError, Result};
+
+/// Decode the first audio track of a FLAC, MP3, Ogg Vorbis, MP4/M4A (AAC) or WAV file
+/// into its channels and sample rate, or of an Ogg Opus file with the `opus` feature.
+/// The container is probed from the content, not the file extension.
+pub(crate) fn decode(source: impl MediaSource + 'static) -> Result<(Vec<Vec<f32>>, usize)> {
+    #[cfg(feature = "opus")]
+    let source = {
+        let mut source = source;
+        if crate::opus::is_ogg_opus(&mut source)? {
+            return crate::opus::decode(source);
+        }
+        source
+    };
+
+    let stream = MediaSourceStream::new(Box::new(source), Default::default());
+    let probed = symphonia::default::get_probe().format(
+        &Hint::new(),
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| BreezeError::UnsupportedAudioFormat("no audio track".to_string()))?;
+    if track.codec_params.codec == CODEC_TYPE_OPUS {
+        return Err(BreezeError::UnsupportedAudioFormat("Opus needs the `opus` feature".to_string()));
+    }
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| BreezeError::AudioDecode("unknown sample rate".to_string()))?;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    // Interleaved, until the channel count is known
+    let mut samples = Vec::new();
+    let mut channels = 1;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame only loses its own samples
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        channels = spec.channels.count();
+        let needed = decoded.capacity() * channels;
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= needed => buffer,
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    Ok((crate::audio::deinterleave(&samples, channels), sample_rate as usize))
+}
+
+impl From<SymphoniaError> for BreezeError {
+    fn from(e: SymphoniaError) -> Self {
+        match e {
+            SymphoniaError::IoError(e) => Self::Io(e),
+            SymphoniaError::Unsupported(what) => Self::UnsupportedAudioFormat(what.to_string()),
+            e => Self::AudioDecode(e.to_string()),
+        }
+    }
+}
@@ -1,18 +1,32 @@
+pub mod alignment;
 pub mod audio;
+pub mod builder;
+pub mod error;
+#[cfg(feature = "formats")]
+mod formats;
 pub mod model;
+#[cfg(feature = "opus")]
+mod opus;
+pub mod session;
 pub mod tokenizer;
+pub mod transcript;
 #[cfg(feature = "stream")]
 pub mod vad;
 
 #[cfg(feature = "stream")]
 pub use vad::VadConfig;
 
-use std::path::Path;
-use anyhow::Result;
-use hf_hub::api::sync::Api;
+use std::borrow::Cow;
+use std::io::{Read, Seek};
 
 use crate::audio::AudioProcessor;
-use crate::model::BreezeModel;
+pub use crate::audio::ChannelPolicy;
+pub use crate::builder::{BreezeASRBuilder, ModelSource};
+pub use crate::error::{BreezeError, Result};
+use crate::model::{BreezeModel, DecodingResult, EncoderOutput};
+pub use crate::model::{DecodingOptions, Task};
+pub use crate::session::{OptimizationLevel, SessionOptions};
+pub use crate::transcript::{Dialogue, Segment, Transcript, Utterance, Word};
 use crate::tokenizer::Tokenizer;
 
 #[cfg(feature = "stream")]
@@ -30,85 +44,428 @@ pub struct BreezeASR {
     model: BreezeModel,
     tokenizer: Tokenizer,
     audio_processor: AudioProcessor,
+    /// Default decoding options, set by the builder.
+    options: DecodingOptions,
+    /// Number of clips `infer_files` runs through the model at once.
+    batch_size: usize,
     #[cfg(feature = "stream")]
     vad_processor: Mutex<Option<VadProcessor>>,
+    /// Settings for VAD runs over whole files, e.g. `infer_dialogue`.
+    #[cfg(feature = "stream")]
+    vad_config: VadConfig,
 }
 
 impl BreezeASR {
-    /// Initialize the model.
+    /// Start configuring a `BreezeASR`.
+    pub fn builder() -> BreezeASRBuilder {
+        BreezeASRBuilder::new()
+    }
+
+    /// Initialize the model with default settings.
     /// If `model_dir` is provided, loads from there.
     /// Otherwise, downloads from Hugging Face.
     pub fn init(model_dir: Option<&str>) -> Result<Self> {
-        let (encoder_path, decoder_path, tokenizer_path) = match model_dir {
-            Some(dir) => {
-                let dir = Path::new(dir);
-                (
-                    dir.join("breeze-asr-25-encoder.onnx"),
-                    dir.join("breeze-asr-25-decoder.onnx"),
-                    dir.join("breeze-asr-25-tokens.txt"),
-                )
-            }
-            None => {
-                let api = Api::new()?;
-                let repo = api.model("MediaTek-Research/Breeze-ASR-25-onnx-250806".to_string());
-                (
-                    repo.get("breeze-asr-25-encoder.onnx")?,
-                    repo.get("breeze-asr-25-decoder.onnx")?,
-                    repo.get("breeze-asr-25-tokens.txt")?,
-                )
-            }
-        };
-
-        if model_dir.is_none() {
-            let api = Api::new()?;
-            let repo = api.model("MediaTek-Research/Breeze-ASR-25-onnx-250806".to_string());
-            let _ = repo.get("breeze-asr-25-encoder.weights")?;
-            let _ = repo.get("breeze-asr-25-decoder.weights")?;
+        let builder = Self::builder();
+        match model_dir {
+            Some(dir) => builder.model_dir(dir).build(),
+            None => builder.build(),
         }
+    }
 
-        let model = BreezeModel::new(
-            encoder_path.to_str().unwrap(), 
-            decoder_path.to_str().unwrap()
-        )?;
-        
-        let tokenizer = Tokenizer::new(tokenizer_path.to_str().unwrap())?;
-        let audio_processor = AudioProcessor::new()?;
+    /// Initialize with custom VAD configuration.
+    #[cfg(feature = "stream")]
+    #[deprecated(note = "use `BreezeASR::builder().vad_config(..)`")]
+    pub fn init_with_vad(model_dir: Option<&str>, vad_config: VadConfig) -> Result<Self> {
+        let builder = Self::builder().vad_config(vad_config);
+        match model_dir {
+            Some(dir) => builder.model_dir(dir).build(),
+            None => builder.build(),
+        }
+    }
 
-        #[cfg(feature = "stream")]
-        let vad_processor = Mutex::new(Some(VadProcessor::new(VadConfig::default())?));
+    /// Transcribe a file of any length with the builder's decoding options.
+    /// The audio is walked in 30s windows, each giving one or more segments.
+    pub fn infer_file(&self, path: &str) -> Result<Transcript> {
+        self.infer_file_with_options(path, &self.options)
+    }
 
-        Ok(Self {
-            model,
-            tokenizer,
-            audio_processor,
-            #[cfg(feature = "stream")]
-            vad_processor,
-        })
+    /// Transcribe a multi-channel file with the builder's decoding options, one
+    /// transcript per channel kept by `policy` (see `ChannelPolicy`).
+    pub fn infer_file_channels(&self, path: &str, policy: ChannelPolicy) -> Result<Vec<Transcript>> {
+        let channels = self.audio_processor.load_channels(path, policy)?;
+        channels
+            .iter()
+            .map(|samples| self.transcribe(samples, &self.options))
+            .collect()
     }
 
-    /// Initialize with custom VAD configuration.
+    /// Transcribe a recording with one speaker per channel, e.g. a call with the agent
+    /// on the left and the customer on the right: `&["Agent", "Customer"]`.
+    ///
+    /// Each channel is split into speech segments by the VAD and transcribed with
+    /// timestamps on its own; the segments are then merged into time-ordered turns.
     #[cfg(feature = "stream")]
-    pub fn init_with_vad(model_dir: Option<&str>, vad_config: VadConfig) -> Result<Self> {
-        let slf = Self::init(model_dir)?;
-        *slf.vad_processor.lock().unwrap() = Some(VadProcessor::new(vad_config)?);
-        Ok(slf)
+    pub fn infer_dialogue(&self, path: &str, speakers: &[&str]) -> Result<Dialogue> {
+        let options = DecodingOptions { timestamps: true, ..self.options.clone() };
+        let channels = self.audio_processor.load_channels(path, ChannelPolicy::Separate)?;
+
+        let transcripts = channels
+            .iter()
+            .map(|samples| {
+                let mut segments = Vec::new();
+                for range in vad::speech_ranges(self.vad_config, samples)? {
+                    let mut transcript = self.transcribe(&samples[range.clone()], &options)?;
+                    transcript.shift(range.start as f32 / audio::SAMPLE_RATE as f32);
+                    segments.extend(transcript.segments);
+                }
+                Ok(Transcript::new(segments))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Dialogue::from_channels(&transcripts, speakers))
     }
 
-    pub fn infer_file(&self, path: &str) -> Result<Vec<String>> {
+    /// Detect the spoken language from the first 30 seconds of a file.
+    /// Returns `(code, probability)` pairs, most likely first, e.g. `[("zh", 0.82), ("en", 0.15), ...]`.
+    pub fn detect_language(&self, path: &str) -> Result<Vec<(String, f32)>> {
         let mel = self.audio_processor.load_and_preprocess(path)?;
-        let tokens = self.model.infer(&mel)?;
-        let text = self.tokenizer.decode(&tokens);
+        self.model.detect_language(&mel)
+    }
+
+    /// Same as `infer_file`, with custom decoding options (e.g. beam search, language or task).
+    pub fn infer_file_with_options(&self, path: &str, options: &DecodingOptions) -> Result<Transcript> {
+        let samples = self.audio_processor.load_audio(path)?;
+        self.transcribe(&samples, options)
+    }
+
+    /// Transcribe a file into segments with start/end times from Whisper's timestamp tokens.
+    /// Set `word_timestamps` in the options to also get per-word timings.
+    pub fn infer_file_with_timestamps(&self, path: &str, options: &DecodingOptions) -> Result<Transcript> {
+        let options = DecodingOptions { timestamps: true, ..options.clone() };
+        let samples = self.audio_processor.load_audio(path)?;
+        self.transcribe(&samples, &options)
+    }
+
+    /// Transcribe mono samples in [-1, 1] with the builder's decoding options,
+    /// resampling them to 16kHz if needed.
+    pub fn infer_samples(&self, samples: &[f32], sample_rate: usize) -> Result<Transcript> {
+        let samples = audio::resample_audio(samples, sample_rate, audio::SAMPLE_RATE)?;
+        self.transcribe(&samples, &self.options)
+    }
+
+    /// Same as `infer_samples`, for 16-bit PCM.
+    pub fn infer_i16(&self, samples: &[i16], sample_rate: usize) -> Result<Transcript> {
+        let samples: Vec<f32> = samples.iter().map(|&x| x as f32 / 32768.0).collect();
+        self.infer_samples(&samples, sample_rate)
+    }
+
+    /// Transcribe an audio file held in memory or received over the network, e.g.
+    /// `std::io::Cursor::new(body)`, without writing it to disk.
+    /// It is read as by `infer_file`.
+    pub fn infer_reader(&self, reader: impl Read + Seek) -> Result<Transcript> {
+        let samples = self.audio_processor.load_reader(reader)?;
+        self.transcribe(&samples, &self.options)
+    }
+
+    /// Transcribe several files with the builder's decoding options, one result per file,
+    /// so a file that can't be read or transcribed does not lose the others.
+    ///
+    /// Files are read `BreezeASRBuilder::batch_size` at a time, so memory use is bounded
+    /// by the batch. Those of at most 30s are encoded and decoded together, which is much
+    /// faster for many short clips. Longer files are transcribed one by one.
+    pub fn infer_files(&self, paths: &[&str]) -> Vec<Result<Transcript>> {
+        paths.chunks(self.batch_size).flat_map(|chunk| self.infer_chunk(chunk)).collect()
+    }
+
+    /// Transcribe one `infer_files` chunk, batching the files of at most 30s.
+    fn infer_chunk(&self, paths: &[&str]) -> Vec<Result<Transcript>> {
+        let samples: Vec<Result<Vec<f32>>> = paths.iter().map(|path| self.audio_processor.load_audio(path)).collect();
+        // An empty file has no window to batch, and gives an empty transcript like `infer_file`
+        let is_short = |samples: &[f32]| !samples.is_empty() && audio::next_window_end(samples, 0) == samples.len();
+        let short: Vec<&[f32]> = samples.iter().flatten().map(Vec::as_slice).filter(|s| is_short(s)).collect();
+
+        let mut batched = match self.transcribe_batch(&short) {
+            Ok(transcripts) => transcripts,
+            // Find out which of the files fail on their own
+            Err(_) => short.iter().map(|s| self.transcribe(s, &self.options)).collect(),
+        }
+        .into_iter();
+
+        samples
+            .into_iter()
+            .map(|samples| {
+                let samples = samples?;
+                if samples.is_empty() {
+                    Ok(Transcript::default())
+                } else if is_short(&samples) {
+                    batched.next().expect("one transcript per short file")
+                } else {
+                    self.transcribe(&samples, &self.options)
+                }
+            })
+            .collect()
+    }
+
+    /// Transcribe clips of at most 30s as one batch. An error of the whole batch is
+    /// returned as such, the error of a single clip in its place.
+    fn transcribe_batch(&self, samples: &[&[f32]]) -> Result<Vec<Result<Transcript>>> {
+        if samples.is_empty() {
+            return Ok(Vec::new());
+        }
+        let options = &self.options;
+        let prompt = self.encode_prompt(options);
+
+        let mels: Vec<_> = samples.iter().map(|s| self.audio_processor.process_pcm(s)).collect();
+        let encoded = self.model.encode_batch(&mels)?;
+        let results = self.model.decode_batch(&encoded, options, &prompt)?;
+
+        Ok(samples
+            .iter()
+            .zip(results)
+            .enumerate()
+            .map(|(index, (samples, result))| self.transcribe_batch_item(&encoded, index, samples, result, &prompt))
+            .collect())
+    }
+
+    /// Finish the clip at `index` of a batch decoded once: retry it if the decoding
+    /// failed, then turn it into segments.
+    fn transcribe_batch_item(
+        &self,
+        encoded: &EncoderOutput,
+        index: usize,
+        samples: &[f32],
+        mut result: DecodingResult,
+        prompt: &[i64],
+    ) -> Result<Transcript> {
+        let options = &self.options;
+        let special = &self.model.dims().tokens;
+
+        // The window is only copied out of the batch to decode it again or align words
+        let item = if options.word_timestamps || self.fallback_temperature(&result, options).is_some() {
+            Some(encoded.item(index)?)
+        } else {
+            None
+        };
+        let encoded = item.as_ref().unwrap_or(encoded);
+        // Keep the language detected for the batch in retries and later windows
+        let mut item_options = options.clone();
+        if item_options.language.is_none() {
+            item_options.language = special.token_language(&result.tokens).map(str::to_string);
+        }
+        let mut retry_options = item_options.clone();
+        while let Some(temperature) = self.fallback_temperature(&result, &retry_options) {
+            retry_options.temperature = temperature;
+            result = self.model.decode(encoded, &retry_options, prompt)?;
+        }
 
-        Ok(vec![text])
+        let (segments, seek) = self.window_segments(encoded, &result, &item_options, 0, samples.len())?;
+        self.transcribe_from(samples, seek, &item_options, segments)
     }
 
-    /// Streaming inference.
+    /// Long-form transcription of 16kHz samples.
+    ///
+    /// Windows are at most 30s. With timestamps enabled, the next window starts at
+    /// the end of the last complete segment; otherwise at the end of the window.
+    fn transcribe(&self, samples: &[f32], options: &DecodingOptions) -> Result<Transcript> {
+        self.transcribe_from(samples, 0, options, Vec::new())
+    }
+
+    /// Go on transcribing from sample `seek`, after the given `segments`.
+    ///
+    /// Without a language in `options`, it is detected once from the first window
+    /// and kept for the rest of the file, as Whisper does.
+    fn transcribe_from(
+        &self,
+        samples: &[f32],
+        mut seek: usize,
+        options: &DecodingOptions,
+        mut segments: Vec<Segment>,
+    ) -> Result<Transcript> {
+        let mut options = Cow::Borrowed(options);
+        let prompt = self.encode_prompt(&options);
+        while seek < samples.len() {
+            let end = audio::next_window_end(samples, seek);
+            let mel = self.audio_processor.process_pcm(&samples[seek..end]);
+            let encoded = self.model.encode(&mel)?;
+            if options.language.is_none() {
+                let language = self.model.detect_languages(&encoded)?.remove(0).remove(0).0;
+                options.to_mut().language = Some(language);
+            }
+            let result = self.decode_with_fallback(&encoded, &options, &prompt)?;
+
+            let (window_segments, next) = self.window_segments(&encoded, &result, &options, seek, end)?;
+            segments.extend(window_segments);
+            seek = next;
+        }
+
+        Ok(Transcript::new(segments))
+    }
+
+    /// Segments of the decoded window `seek..end`, and the sample the next window starts at.
+    fn window_segments(
+        &self,
+        encoded: &EncoderOutput,
+        result: &DecodingResult,
+        options: &DecodingOptions,
+        seek: usize,
+        end: usize,
+    ) -> Result<(Vec<Segment>, usize)> {
+        let special = &self.model.dims().tokens;
+        let tokens = &result.tokens;
+        let mut segments = Vec::new();
+
+        let language = special.token_language(tokens).map(str::to_string);
+        let offset = seek as f32 / audio::SAMPLE_RATE as f32;
+        let duration = (end - seek) as f32 / audio::SAMPLE_RATE as f32;
+
+        if result.is_silence(options) {
+            // Nothing was said, skip the whole window
+            return Ok((segments, end));
+        }
+
+        if options.timestamps || options.word_timestamps {
+            let begin = special.sample_begin(tokens);
+            let (pieces, consumed) = transcript::split_at_timestamps(&tokens[begin..], special.timestamp_begin, duration);
+
+            // Pieces are consecutive slices of the sampled tokens
+            let mut piece_start = begin;
+            let (text_tokens, text_logprobs): (Vec<Vec<i64>>, Vec<Vec<f32>>) = pieces
+                .iter()
+                .map(|p| {
+                    let logprobs = &result.token_logprobs[piece_start..piece_start + p.tokens.len()];
+                    piece_start += p.tokens.len();
+                    p.tokens
+                        .iter()
+                        .zip(logprobs)
+                        .filter(|(&t, _)| special.is_text_token(t))
+                        .unzip()
+                })
+                .unzip();
+
+            let token_times = if options.word_timestamps {
+                let all_text: Vec<i64> = text_tokens.concat();
+                // Like Whisper, align against the SOT sequence only, not the prompt
+                let sot = tokens.iter().position(|&t| t == special.sot).unwrap_or(0);
+                self.model.align(encoded, &tokens[sot..begin], &all_text, duration)?
+            } else {
+                None
+            };
+
+            let mut text_offset = 0;
+            for ((piece, text_tokens), logprobs) in pieces.iter().zip(&text_tokens).zip(&text_logprobs) {
+                let start = offset + piece.start;
+                let end = offset + piece.end;
+
+                let words = if options.word_timestamps {
+                    let words = self.tokenizer.split_words(text_tokens);
+                    let times: Option<Vec<f32>> = token_times
+                        .as_ref()
+                        .and_then(|t| t.get(text_offset..=text_offset + text_tokens.len()))
+                        .map(|t| t.iter().map(|x| offset + x).collect());
+                    transcript::time_words(&words, logprobs, times.as_deref(), start, end)
+                } else {
+                    Vec::new()
+                };
+                text_offset += text_tokens.len();
+
+                segments.push(Segment {
+                    start,
+                    end,
+                    text: self.tokenizer.decode(text_tokens),
+                    tokens: text_tokens.clone(),
+                    language: language.clone(),
+                    no_speech_prob: result.no_speech_prob,
+                    confidence: transcript::confidence(logprobs),
+                    words,
+                });
+            }
+
+            let consumed_samples = (consumed * audio::SAMPLE_RATE as f32) as usize;
+            let next = if consumed_samples > 0 { (seek + consumed_samples).min(end) } else { end };
+            Ok((segments, next))
+        } else {
+            let begin = special.sample_begin(tokens);
+            let (text_tokens, logprobs): (Vec<i64>, Vec<f32>) = tokens[begin..]
+                .iter()
+                .zip(&result.token_logprobs[begin..])
+                .filter(|(&t, _)| special.is_text_token(t))
+                .unzip();
+            segments.push(Segment {
+                start: offset,
+                end: offset + duration,
+                text: self.tokenizer.decode(&text_tokens),
+                tokens: text_tokens,
+                language,
+                no_speech_prob: result.no_speech_prob,
+                confidence: transcript::confidence(&logprobs),
+                words: Vec::new(),
+            });
+            Ok((segments, end))
+        }
+    }
+
+    /// Decode a window, retrying at higher temperatures while the result looks like
+    /// a repetition loop or is too unlikely. The last attempt is returned if all fail.
+    fn decode_with_fallback(&self, encoded: &EncoderOutput, options: &DecodingOptions, prompt: &[i64]) -> Result<DecodingResult> {
+        let mut options = options.clone();
+        loop {
+            let result = self.model.decode(encoded, &options, prompt)?;
+            match self.fallback_temperature(&result, &options) {
+                Some(t) => options.temperature = t,
+                None => return Ok(result),
+            }
+        }
+    }
+
+    /// Temperature to decode at again, if `result` failed and there is one left.
+    /// Silent windows are not retried, since the caller skips them anyway.
+    fn fallback_temperature(&self, result: &DecodingResult, options: &DecodingOptions) -> Option<f32> {
+        // A repetitive prompt must not make the sampled text look like a loop
+        let text = self.tokenizer.decode(&result.text_tokens(&self.model.dims().tokens));
+        let too_repetitive = options
+            .compression_ratio_threshold
+            .is_some_and(|threshold| transcript::compression_ratio(&text) > threshold);
+        let too_unlikely = options
+            .logprob_threshold
+            .is_some_and(|threshold| result.avg_logprob < threshold);
+
+        options
+            .temperature_increment
+            .map(|increment| options.temperature + increment)
+            .filter(|&t| t <= 1.0 + f32::EPSILON)
+            .filter(|_| (too_repetitive || too_unlikely) && !result.is_silence(options))
+    }
+
+    fn encode_prompt(&self, options: &DecodingOptions) -> Vec<i64> {
+        match &options.prompt {
+            // Whisper prompts start with a space, like a continuation of earlier text
+            Some(prompt) => self.tokenizer.encode(&format!(" {}", prompt.trim())),
+            None => Vec::new(),
+        }
+    }
+
+    /// Streaming inference with the builder's decoding options, yielding one transcript
+    /// per speech segment found by the VAD.
     /// Filters out empty or silence-only segments.
     #[cfg(feature = "stream")]
     pub fn infer_stream<'a, S>(
         &'a self,
         input_stream: S,
-    ) -> impl Stream<Item = Result<String>> + 'a
+    ) -> impl Stream<Item = Result<Transcript>> + 'a
+    where
+        S: Stream<Item = Vec<i16>> + Unpin + 'a,
+    {
+        self.infer_stream_with_options(input_stream, self.options.clone())
+    }
+
+    /// Same as `infer_stream`, with custom decoding options (e.g. a forced language).
+    /// A failed segment yields an error and the stream goes on with the next one.
+    #[cfg(feature = "stream")]
+    pub fn infer_stream_with_options<'a, S>(
+        &'a self,
+        input_stream: S,
+        options: DecodingOptions,
+    ) -> impl Stream<Item = Result<Transcript>> + 'a
     where
         S: Stream<Item = Vec<i16>> + Unpin + 'a,
     {
@@ -122,67 +479,49 @@ impl BreezeASR {
 
                 let chunk_arr: &[i16; CHUNK_SIZE] = chunk.as_slice().try_into().unwrap();
                 
-                let output_opt = {
-                    let mut vad_guard = self.vad_processor.lock().unwrap();
-                    if let Some(vad) = vad_guard.as_mut() {
-                        vad.process_chunk(chunk_arr)
-                    } else {
-                        None // Should return error maybe?
-                    }
+                let output = match self.vad_processor.lock() {
+                    Ok(mut vad_guard) => match vad_guard.as_mut() {
+                        Some(vad) => Ok(vad.process_chunk(chunk_arr)),
+                        None => Err(BreezeError::Stream("VAD is not initialized".to_string())),
+                    },
+                    Err(_) => Err(BreezeError::Poisoned("VAD processor")),
                 };
 
-                if let Some(output) = output_opt {
-                    match output {
-                        VadOutput::Segment(segment) => {
-                            if let Ok(text) = self.infer_segment(&segment) {
-                                if !text.trim().is_empty() {
-                                    yield Ok(text);
-                                }
-                            }
-                        },
-                        VadOutput::SilenceNotification => {}
+                match output {
+                    Ok(Some(VadOutput::Segment(segment))) => match self.infer_segment(&segment, &options) {
+                        Ok(transcript) if transcript.is_empty() => {}
+                        result => yield result,
+                    },
+                    Ok(_) => {}
+                    Err(e) => {
+                        yield Err(e);
+                        return;
                     }
                 }
             }
             
-            let finish_opt = {
-                 let mut vad_guard = self.vad_processor.lock().unwrap();
-                 if let Some(vad) = vad_guard.as_mut() {
-                     vad.finish()
-                 } else {
-                     None
-                 }
+            let finish = match self.vad_processor.lock() {
+                Ok(mut vad_guard) => Ok(vad_guard.as_mut().and_then(|vad| vad.finish())),
+                Err(_) => Err(BreezeError::Poisoned("VAD processor")),
             };
 
-            if let Some(output) = finish_opt {
-                 match output {
-                    VadOutput::Segment(segment) => {
-                        if let Ok(text) = self.infer_segment(&segment) {
-                            if !text.trim().is_empty() {
-                                yield Ok(text);
-                            }
-                        }
-                    },
-                    VadOutput::SilenceNotification => {}
-                }
+            match finish {
+                Ok(Some(VadOutput::Segment(segment))) => match self.infer_segment(&segment, &options) {
+                    Ok(transcript) if transcript.is_empty() => {}
+                    result => yield result,
+                },
+                Ok(_) => {}
+                Err(e) => yield Err(e),
             }
         }
     }
 
     #[cfg(feature = "stream")]
-    fn infer_segment(&self, segment: &[i16]) -> Result<String> {
+    fn infer_segment(&self, segment: &[i16], options: &DecodingOptions) -> Result<Transcript> {
         // Convert i16 to f32 normalized
         let samples: Vec<f32> = segment.iter().map(|&x| x as f32 / 32768.0).collect();
-        
-        // Preprocess
-        let mel = self.audio_processor.process_pcm(&samples);
-        
-        // Infer
-        let tokens = self.model.infer(&mel)?;
-        
-        // Decode
-        let text = self.tokenizer.decode(&tokens);
-        
-        Ok(text)
+
+        // Segments are usually shorter than a window, but long ones are split too
+        self.transcribe(&samples, options)
     }
 }
@@ -1,142 +1,1115 @@
-use std::sync::Mutex;
 use std::collections::HashMap;
-use anyhow::{Result, anyhow};
+use std::path::Path;
 use ort::{
-    session::{Session, builder::GraphOptimizationLevel, SessionInputValue},
-    value::Tensor,
+    session::{Session, SessionInputValue, SessionOutputs},
+    value::{DynValue, Tensor, TensorValueType, ValueType},
 };
-use ndarray::{Array1, Array2, Array3, Array4, Axis};
+use ndarray::{Array1, Array2, Array3, Array4, ArrayView2, Axis, Ix3};
+use rand::Rng;
 
-const SOT: i64 = 50258;
-const EOT: i64 = 50257;
-const MAX_LEN: usize = 448;
-const N_LAYER: usize = 32;
-const D_MODEL: usize = 1280;
+use crate::error::{BreezeError, Result};
+use crate::session::{SessionOptions, SessionPool};
 
+/// Latest timestamp the first sampled token may be, in steps of `TIME_PRECISION` (1s).
+pub const MAX_INITIAL_TIMESTAMP_INDEX: i64 = 50;
+
+// Large-v2 (Breeze ASR 25), used when the graph leaves a dimension dynamic
+const DEFAULT_N_LAYER: usize = 32;
+const DEFAULT_D_MODEL: usize = 1280;
+const DEFAULT_N_CTX: usize = 448;
+const DEFAULT_N_VOCAB: usize = 51865;
+const DEFAULT_N_MELS: usize = 80;
+const DEFAULT_N_FRAMES: usize = 3000;
+
+/// Language codes in token order, starting at `SpecialTokens::first_language`.
+/// Vocabularies before large-v3 have all but the last one.
+pub const LANGUAGES: [&str; 100] = [
+    "en", "zh", "de", "es", "ru", "ko", "fr", "ja", "pt", "tr", "pl", "ca", "nl", "ar", "sv",
+    "it", "id", "hi", "fi", "vi", "he", "uk", "el", "ms", "cs", "ro", "da", "hu", "ta", "no",
+    "th", "ur", "hr", "bg", "lt", "la", "mi", "ml", "cy", "sk", "te", "fa", "lv", "bn", "sr",
+    "az", "sl", "kn", "et", "mk", "br", "eu", "is", "hy", "ne", "mn", "bs", "kk", "sq", "sw",
+    "gl", "mr", "pa", "si", "km", "sn", "yo", "so", "af", "oc", "ka", "be", "tg", "sd", "gu",
+    "am", "yi", "lo", "uz", "fo", "ht", "ps", "tk", "nn", "mt", "sa", "lb", "my", "bo", "tl",
+    "mg", "as", "tt", "haw", "ln", "ha", "ba", "jw", "su", "yue",
+];
+
+/// Special token ids of a Whisper vocabulary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecialTokens {
+    pub eot: i64,
+    pub sot: i64,
+    /// First language token, followed by the others in `LANGUAGES` order.
+    pub first_language: i64,
+    pub n_languages: usize,
+    pub translate: i64,
+    pub transcribe: i64,
+    pub sot_prev: i64,
+    pub no_speech: i64,
+    pub no_timestamps: i64,
+    /// `<|0.00|>`; every later id is a timestamp too.
+    pub timestamp_begin: i64,
+    /// Whether the SOT sequence has language and task tokens (false for `.en` models).
+    pub multilingual: bool,
+}
+
+impl SpecialTokens {
+    /// Lay out the special tokens after the text tokens like Whisper's tokenizer.
+    /// `n_vocab` tells the vocabulary apart: 51864 for `.en` models, 51865 for
+    /// multilingual ones and 51866 for large-v3, which added a language.
+    pub fn from_vocab_size(n_vocab: usize) -> Self {
+        let multilingual = n_vocab >= 51865;
+        let n_languages = n_vocab.saturating_sub(51765 + multilingual as usize).min(LANGUAGES.len());
+        let eot = 50256 + multilingual as i64;
+        let sot = eot + 1;
+        let first_language = sot + 1;
+        let translate = first_language + n_languages as i64;
+        // <|startoflm|> sits between transcribe and startofprev
+        let sot_prev = translate + 3;
+        Self {
+            eot,
+            sot,
+            first_language,
+            n_languages,
+            translate,
+            transcribe: translate + 1,
+            sot_prev,
+            no_speech: sot_prev + 1,
+            no_timestamps: sot_prev + 2,
+            timestamp_begin: sot_prev + 3,
+            multilingual,
+        }
+    }
+
+    /// Language codes the vocabulary has tokens for.
+    pub fn languages(&self) -> &'static [&'static str] {
+        &LANGUAGES[..self.n_languages]
+    }
+
+    pub fn language_token(&self, language: &str) -> Result<i64> {
+        self.languages()
+            .iter()
+            .position(|&code| code == language)
+            .map(|i| self.first_language + i as i64)
+            .ok_or_else(|| BreezeError::UnsupportedLanguage(language.to_string()))
+    }
+
+    /// Language code of the language token in the decoded prompt, if any.
+    pub(crate) fn token_language(&self, tokens: &[i64]) -> Option<&'static str> {
+        tokens
+            .iter()
+            .take(self.sample_begin(tokens))
+            .find_map(|&t| self.languages().get(usize::try_from(t - self.first_language).ok()?).copied())
+    }
+
+    /// Whether `token` is regular text, as opposed to a special or timestamp token.
+    pub(crate) fn is_text_token(&self, token: i64) -> bool {
+        token < self.eot
+    }
+
+    /// Index of the first sampled token, right after the SOT sequence.
+    pub(crate) fn sample_begin(&self, tokens: &[i64]) -> usize {
+        let Some(sot) = tokens.iter().position(|&t| t == self.sot) else {
+            return 1;
+        };
+        let is_sot_token = |t: i64| {
+            (self.first_language..=self.transcribe).contains(&t) || t == self.no_timestamps
+        };
+        sot + 1 + tokens[sot + 1..].iter().take(3).take_while(|&&t| is_sot_token(t)).count()
+    }
+
+    /// `<|startoftranscript|><|lang|><|task|>`, plus `<|notimestamps|>` when timestamps are off.
+    /// `.en` models have no language and task tokens. A non-empty prompt goes before it as
+    /// `<|startofprev|>` and the last `max_prompt_len` prompt tokens.
+    pub fn sot_sequence(&self, language: i64, options: &DecodingOptions, prompt: &[i64], max_prompt_len: usize) -> Vec<i64> {
+        let task = match options.task {
+            Task::Transcribe => self.transcribe,
+            Task::Translate => self.translate,
+        };
+        let mut sequence = Vec::new();
+        if !prompt.is_empty() {
+            sequence.push(self.sot_prev);
+            sequence.extend_from_slice(&prompt[prompt.len().saturating_sub(max_prompt_len)..]);
+        }
+        sequence.push(self.sot);
+        if self.multilingual {
+            sequence.extend_from_slice(&[language, task]);
+        }
+        if !options.timestamps {
+            sequence.push(self.no_timestamps);
+        }
+        sequence
+    }
+
+    /// Whisper's timestamp rules, applied to the logits of the next token.
+    /// `tokens` is the whole sequence so far, starting with the prompt and SOT sequence.
+    pub fn apply_timestamp_rules(&self, logits: &mut [f32], tokens: &[i64]) {
+        let sampled = &tokens[self.sample_begin(tokens)..];
+        let ts_begin = self.timestamp_begin as usize;
+        let eot = self.eot as usize;
+
+        // Timestamp mode is handled here, never let the model opt out of it
+        logits[self.no_timestamps as usize] = f32::NEG_INFINITY;
+
+        // Timestamps come in pairs, except directly before EOT
+        let last_was_timestamp = sampled.last().is_some_and(|&t| t >= self.timestamp_begin);
+        let penultimate_was_timestamp = sampled.len() < 2 || sampled[sampled.len() - 2] >= self.timestamp_begin;
+        if last_was_timestamp {
+            if penultimate_was_timestamp {
+                logits[ts_begin..].fill(f32::NEG_INFINITY);
+            } else {
+                logits[..eot].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        // Timestamps must not decrease, and segments must have a non-zero length
+        if let Some(&last_ts) = sampled.iter().rev().find(|&&t| t >= self.timestamp_begin) {
+            let last_allowed = if last_was_timestamp && !penultimate_was_timestamp {
+                last_ts
+            } else {
+                last_ts + 1
+            };
+            let end = (last_allowed as usize).min(logits.len());
+            logits[ts_begin..end].fill(f32::NEG_INFINITY);
+        }
+
+        // The first sampled token is a timestamp, and not too far into the window
+        if sampled.is_empty() {
+            logits[..ts_begin].fill(f32::NEG_INFINITY);
+            let last_allowed = ts_begin + MAX_INITIAL_TIMESTAMP_INDEX as usize;
+            if last_allowed + 1 < logits.len() {
+                logits[last_allowed + 1..].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        // Prefer a timestamp when all timestamps together outweigh the best text token
+        let logprobs = log_softmax(logits);
+        let max_ts = logprobs[ts_begin..].iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        let timestamp_logprob = max_ts
+            + logprobs[ts_begin..].iter().map(|&x| (x - max_ts).exp()).sum::<f32>().ln();
+        let max_text_logprob = logprobs[..ts_begin].iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        if timestamp_logprob > max_text_logprob {
+            logits[..ts_begin].fill(f32::NEG_INFINITY);
+        }
+    }
+}
+
+impl Default for SpecialTokens {
+    fn default() -> Self {
+        Self::from_vocab_size(DEFAULT_N_VOCAB)
+    }
+}
+
+/// Sizes and special tokens of a loaded model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelDims {
+    /// Number of decoder layers.
+    pub n_layer: usize,
+    /// Width of the decoder.
+    pub d_model: usize,
+    /// Maximum number of tokens in the decoder context.
+    pub n_ctx: usize,
+    pub n_vocab: usize,
+    /// Mel bins the encoder expects: 80, or 128 for large-v3 based models.
+    pub n_mels: usize,
+    /// Spectrogram frames per window the encoder expects.
+    pub n_frames: usize,
+    pub tokens: SpecialTokens,
+}
+
+impl ModelDims {
+    /// Read the dimensions from the encoder's `mel` input `[batch, n_mels, n_frames]` and
+    /// the decoder's self-attention cache `[n_layer, batch, n_ctx, d_model]` and logits
+    /// `[batch, n_tokens, n_vocab]` shapes. Dynamic dimensions are taken from the model
+    /// metadata (`n_mels`, `n_text_layer`, `n_text_state`, `n_text_ctx`, `n_vocab`), or
+    /// else default to large-v2.
+    /// Special token ids in the metadata (`sot`, `eot`, ...) override the ones
+    /// derived from the vocabulary size.
+    fn from_sessions(encoder: &Session, decoder: &Session) -> Result<Self> {
+        let metadata = |key: &str| -> Result<Option<i64>> {
+            for session in [decoder, encoder] {
+                if let Some(value) = session.metadata()?.custom(key)? {
+                    return Ok(value.trim().parse().ok());
+                }
+            }
+            Ok(None)
+        };
+        let input_dim = |name: &str, axis: usize| tensor_dim(decoder.inputs.iter().find(|i| i.name == name).map(|i| &i.input_type), axis);
+        let mel_dim = |axis: usize| tensor_dim(encoder.inputs.iter().find(|i| i.name == "mel").map(|i| &i.input_type), axis);
+        let output_dim = |name: &str, axis: usize| tensor_dim(decoder.outputs.iter().find(|o| o.name == name).map(|o| &o.output_type), axis);
+        let resolve = |dim: Option<usize>, key: &str, default: usize| -> Result<usize> {
+            match dim {
+                Some(dim) => Ok(dim),
+                None => Ok(metadata(key)?.and_then(|v| usize::try_from(v).ok()).unwrap_or(default)),
+            }
+        };
+
+        let n_layer = resolve(input_dim("in_n_layer_self_k_cache", 0), "n_text_layer", DEFAULT_N_LAYER)?;
+        let n_ctx = resolve(input_dim("in_n_layer_self_k_cache", 2), "n_text_ctx", DEFAULT_N_CTX)?;
+        let d_model = resolve(input_dim("in_n_layer_self_k_cache", 3), "n_text_state", DEFAULT_D_MODEL)?;
+        let n_vocab = resolve(output_dim("logits", 2), "n_vocab", DEFAULT_N_VOCAB)?;
+        let n_mels = resolve(mel_dim(1), "n_mels", DEFAULT_N_MELS)?;
+        // Encoders take twice as many frames as they have positions
+        let n_frames = match mel_dim(2) {
+            Some(frames) => frames,
+            None => metadata("n_audio_ctx")?
+                .and_then(|ctx| usize::try_from(ctx * 2).ok())
+                .unwrap_or(DEFAULT_N_FRAMES),
+        };
+
+        let mut tokens = SpecialTokens::from_vocab_size(n_vocab);
+        for (key, id) in [
+            ("eot", &mut tokens.eot),
+            ("sot", &mut tokens.sot),
+            ("translate", &mut tokens.translate),
+            ("transcribe", &mut tokens.transcribe),
+            ("sot_prev", &mut tokens.sot_prev),
+            ("no_speech", &mut tokens.no_speech),
+            ("no_timestamps", &mut tokens.no_timestamps),
+        ] {
+            if let Some(value) = metadata(key)? {
+                *id = value;
+            }
+        }
+        if let Some(multilingual) = metadata("is_multilingual")? {
+            tokens.multilingual = multilingual != 0;
+        }
+        tokens.first_language = tokens.sot + 1;
+        tokens.n_languages = usize::try_from(tokens.translate - tokens.first_language)
+            .unwrap_or(0)
+            .min(LANGUAGES.len());
+        tokens.timestamp_begin = tokens.no_timestamps + 1;
+
+        Ok(Self { n_layer, d_model, n_ctx, n_vocab, n_mels, n_frames, tokens })
+    }
+
+    /// Prompt tokens kept after `<|startofprev|>`, half of the context minus that token.
+    pub fn max_prompt_len(&self) -> usize {
+        self.n_ctx / 2 - 1
+    }
+}
+
+impl Default for ModelDims {
+    fn default() -> Self {
+        Self {
+            n_layer: DEFAULT_N_LAYER,
+            d_model: DEFAULT_D_MODEL,
+            n_ctx: DEFAULT_N_CTX,
+            n_vocab: DEFAULT_N_VOCAB,
+            n_mels: DEFAULT_N_MELS,
+            n_frames: DEFAULT_N_FRAMES,
+            tokens: SpecialTokens::default(),
+        }
+    }
+}
+
+/// A static dimension of a tensor input or output, `None` if it is dynamic.
+fn tensor_dim(value_type: Option<&ValueType>, axis: usize) -> Option<usize> {
+    let shape = value_type?.tensor_shape()?;
+    usize::try_from(*shape.get(axis)?).ok().filter(|&d| d > 0)
+}
+
+/// Decoder output with raw cross-attention scores `[n_layer, batch, n_head, n_tokens, n_audio_ctx]`.
+/// Exports without it (like the official Breeze ONNX files) fall back to estimated word timings.
+const CROSS_QK_OUTPUT: &str = "n_layer_cross_qk";
+
+/// Whether to transcribe in the spoken language or translate to English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Task {
+    #[default]
+    Transcribe,
+    Translate,
+}
+
+/// Options controlling how `BreezeModel` decodes tokens.
+#[derive(Debug, Clone)]
+pub struct DecodingOptions {
+    /// Language code to force, e.g. `zh` or `en`. `None` detects it first.
+    pub language: Option<String>,
+    pub task: Task,
+    /// Number of beams for beam search. `None` uses greedy decoding.
+    pub beam_size: Option<usize>,
+    /// Beam search stops once `beam_size * patience` candidates have finished.
+    /// `None` means a patience of 1.0.
+    pub patience: Option<f32>,
+    /// Length penalty used to rank finished beams.
+    /// `None` divides the log probability by the length, `Some(alpha)` uses
+    /// the Google NMT penalty `((5 + length) / 6) ^ alpha`.
+    pub length_penalty: Option<f32>,
+    /// Predict `<|x.xx|>` timestamp tokens, applying Whisper's timestamp rules.
+    pub timestamps: bool,
+    /// Compute per-word timings. Implies `timestamps`.
+    pub word_timestamps: bool,
+    /// Text to condition on, such as a glossary or the previous transcript.
+    /// It is encoded by `BreezeASR`; when calling `BreezeModel` directly, pass the
+    /// encoded tokens to `infer_with_prompt`.
+    pub prompt: Option<String>,
+    /// Sampling temperature. 0 picks the most likely token (or uses beam search).
+    pub temperature: f32,
+    /// When a result fails the thresholds below, `BreezeASR` decodes again with the
+    /// temperature raised by this much, up to 1.0. `None` disables the fallback.
+    pub temperature_increment: Option<f32>,
+    /// Results whose gzip compression ratio is above this are treated as repetitive.
+    pub compression_ratio_threshold: Option<f32>,
+    /// Results whose average log probability is below this are treated as failed.
+    pub logprob_threshold: Option<f32>,
+    /// Windows whose `<|nospeech|>` probability is above this, and whose average log
+    /// probability is below `logprob_threshold`, are treated as silence and skipped.
+    pub no_speech_threshold: Option<f32>,
+}
+
+impl DecodingOptions {
+    /// Check that the options are in range. The language is checked against the model when decoding.
+    pub fn validate(&self) -> Result<()> {
+        if self.beam_size == Some(0) {
+            return Err(BreezeError::InvalidConfig("beam size must be at least 1".to_string()));
+        }
+        if self.patience.is_some_and(|p| p <= 0.0 || !p.is_finite()) {
+            return Err(BreezeError::InvalidConfig("patience must be positive".to_string()));
+        }
+        if !(0.0..=1.0).contains(&self.temperature) {
+            return Err(BreezeError::InvalidConfig(format!("temperature must be in [0, 1], got {}", self.temperature)));
+        }
+        if self.temperature_increment.is_some_and(|i| i <= 0.0 || !i.is_finite()) {
+            return Err(BreezeError::InvalidConfig("temperature increment must be positive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Default for DecodingOptions {
+    fn default() -> Self {
+        Self {
+            language: None,
+            task: Task::default(),
+            beam_size: None,
+            patience: None,
+            length_penalty: None,
+            timestamps: false,
+            word_timestamps: false,
+            prompt: None,
+            temperature: 0.0,
+            temperature_increment: Some(0.2),
+            compression_ratio_threshold: Some(2.4),
+            logprob_threshold: Some(-1.0),
+            no_speech_threshold: Some(0.6),
+        }
+    }
+}
+
+/// Decoded tokens of one window.
+#[derive(Debug, Clone)]
+pub struct DecodingResult {
+    /// The whole sequence, starting with the prompt and SOT sequence.
+    pub tokens: Vec<i64>,
+    /// Log probability of each token in `tokens`, 0 for the prompt and SOT sequence.
+    pub token_logprobs: Vec<f32>,
+    /// Average log probability of the sampled tokens (including the final EOT).
+    pub avg_logprob: f32,
+    /// Temperature the tokens were sampled at.
+    pub temperature: f32,
+    /// Probability of `<|nospeech|>` right after `<|startoftranscript|>`.
+    pub no_speech_prob: f32,
+}
+
+impl DecodingResult {
+    /// Whether the window looks like silence: a high no-speech probability and,
+    /// unless `logprob_threshold` is unset, a low average log probability.
+    pub fn is_silence(&self, options: &DecodingOptions) -> bool {
+        let no_speech = options
+            .no_speech_threshold
+            .is_some_and(|threshold| self.no_speech_prob > threshold);
+        let unlikely = options
+            .logprob_threshold
+            .is_none_or(|threshold| self.avg_logprob < threshold);
+        no_speech && unlikely
+    }
+
+    /// The sampled text tokens, without the prompt, SOT sequence, timestamps or EOT.
+    pub fn text_tokens(&self, special: &SpecialTokens) -> Vec<i64> {
+        self.tokens[special.sample_begin(&self.tokens)..]
+            .iter()
+            .copied()
+            .filter(|&t| special.is_text_token(t))
+            .collect()
+    }
+}
+
+/// Encoder output of one window, or of a batch of windows, which can be decoded several times.
+pub struct EncoderOutput {
+    cross_k: Tensor<f32>,
+    cross_v: Tensor<f32>,
+}
+
+impl EncoderOutput {
+    /// Number of windows, the second axis of `[n_layer, batch, n_audio_ctx, d_model]`.
+    pub fn batch_size(&self) -> usize {
+        self.cross_k.shape()[1] as usize
+    }
+
+    /// Copy out the output of the window at `index`.
+    pub fn item(&self, index: usize) -> Result<EncoderOutput> {
+        let copy = |value: &Tensor<f32>| -> Result<Tensor<f32>> {
+            let item = value.extract_array().index_axis(Axis(1), index).insert_axis(Axis(1)).to_owned();
+            Ok(Tensor::from_array(item)?)
+        };
+        Ok(EncoderOutput { cross_k: copy(&self.cross_k)?, cross_v: copy(&self.cross_v)? })
+    }
+}
+
+/// Encoder and decoder sessions. Each call takes a session from the pool, so up to
+/// `SessionOptions::pool_size` threads can run the model at once.
 pub struct BreezeModel {
-    encoder: Mutex<Session>,
-    decoder: Mutex<Session>,
+    encoder: SessionPool,
+    decoder: SessionPool,
+    has_cross_qk: bool,
+    dims: ModelDims,
+}
+
+/// Self-attention KV cache of one decoder sequence, or of a batch of them.
+///
+/// The caches stay in ort-owned values: the decoder's output caches are fed back
+/// as the next step's inputs, so a step does not copy them.
+struct KvCache {
+    k: DynValue,
+    v: DynValue,
+}
+
+impl KvCache {
+    fn new(dims: &ModelDims, batch: usize) -> Result<Self> {
+        let zeros = || -> Result<DynValue> {
+            let shape = (dims.n_layer, batch, dims.n_ctx, dims.d_model);
+            Ok(Tensor::from_array(Array4::<f32>::zeros(shape))?.into_dyn())
+        };
+        Ok(Self { k: zeros()?, v: zeros()? })
+    }
+
+    /// Copy the cache, for a beam with several children.
+    fn fork(&self) -> Result<Self> {
+        let copy = |value: &DynValue| -> Result<DynValue> {
+            let (shape, data) = value.try_extract_tensor::<f32>()?;
+            Ok(Tensor::from_array((shape.to_vec(), data.to_vec()))?.into_dyn())
+        };
+        Ok(Self { k: copy(&self.k)?, v: copy(&self.v)? })
+    }
+}
+
+struct Beam {
+    tokens: Vec<i64>,
+    logprobs: Vec<f32>,
+    sum_logprob: f32,
+    cache: KvCache,
+}
+
+/// Output of a search over one window.
+struct Searched {
+    tokens: Vec<i64>,
+    logprobs: Vec<f32>,
+    sum_logprob: f32,
+    no_speech_prob: f32,
 }
 
 impl BreezeModel {
     pub fn new(encoder_path: &str, decoder_path: &str) -> Result<Self> {
-        let encoder = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_intra_threads(4)?
-            .commit_from_file(encoder_path)?;
-
-        let decoder = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_intra_threads(4)?
-            .commit_from_file(decoder_path)?;
-
-        Ok(Self { 
-            encoder: Mutex::new(encoder), 
-            decoder: Mutex::new(decoder) 
-        })
+        Self::with_options(encoder_path, decoder_path, &SessionOptions::default())
     }
 
-    pub fn infer(&self, mel: &Array2<f32>) -> Result<Vec<i64>> {
-        // === 1. Encoder ===
-        let batch_mel = mel.view().insert_axis(Axis(0));
-        
-        let (cross_k, cross_v) = {
-            let inputs = ort::inputs![
-                "mel" => Tensor::from_array(batch_mel.to_owned())?,
-            ];
-
-            let mut encoder_session = self.encoder.lock().map_err(|e| anyhow!("Failed to lock encoder: {}", e))?;
-            let encoder_out = encoder_session.run(inputs)?;
-            
-            // Helper to convert output to owned Tensor
-            fn extract_to_tensor(out: &ort::value::DynValue) -> Result<Tensor<f32>> {
-                let (shape, data) = out.try_extract_tensor::<f32>()?;
-                let shape_vec: Vec<usize> = shape.iter().map(|&x| x as usize).collect();
-                // We know it is 4D [32, 1, 1500, 1280] usually
-                let array = Array4::from_shape_vec(
-                    (shape_vec[0], shape_vec[1], shape_vec[2], shape_vec[3]),
-                    data.to_vec()
-                )?;
-                Ok(Tensor::from_array(array)?)
-            }
+    /// Load the encoder and decoder with custom ONNX Runtime settings.
+    pub fn with_options(encoder_path: &str, decoder_path: &str, options: &SessionOptions) -> Result<Self> {
+        let encoder = options.commit_pool(Path::new(encoder_path), "encoder session")?;
+        let decoder = options.commit_pool(Path::new(decoder_path), "decoder session")?;
 
-            let k_owned = extract_to_tensor(&encoder_out["n_layer_cross_k"])?;
-            let v_owned = extract_to_tensor(&encoder_out["n_layer_cross_v"])?;
-            
-            (k_owned, v_owned)
+        let (has_cross_qk, dims) = {
+            let (encoder, decoder) = (encoder.get()?, decoder.get()?);
+            let has_cross_qk = decoder.outputs.iter().any(|o| o.name == CROSS_QK_OUTPUT);
+            (has_cross_qk, ModelDims::from_sessions(&encoder, &decoder)?)
         };
 
-        // === 2. Decoder Loop (Greedy) ===
-        let mut tokens = vec![SOT]; 
-        
-        let mut decoder_session = self.decoder.lock().map_err(|e| anyhow!("Failed to lock decoder: {}", e))?;
-
-        let mut self_k_cache = Array4::<f32>::zeros((N_LAYER, 1, MAX_LEN, D_MODEL));
-        let mut self_v_cache = Array4::<f32>::zeros((N_LAYER, 1, MAX_LEN, D_MODEL));
-
-        for i in 0..MAX_LEN {
-            let offset = i as i64;
-            let current_token = *tokens.last().unwrap();
-            let token_input = Array2::from_shape_vec((1, 1), vec![current_token])?;
-            let offset_input = Array1::from_shape_vec((1,), vec![offset])?;
-
-            let mut inputs: HashMap<String, SessionInputValue<'_>> = HashMap::new();
-            inputs.insert("tokens".to_string(), Tensor::from_array(token_input)?.into());
-            inputs.insert("in_n_layer_self_k_cache".to_string(), Tensor::from_array(self_k_cache.clone())?.into());
-            inputs.insert("in_n_layer_self_v_cache".to_string(), Tensor::from_array(self_v_cache.clone())?.into());
-            inputs.insert("n_layer_cross_k".to_string(), cross_k.clone().into());
-            inputs.insert("n_layer_cross_v".to_string(), cross_v.clone().into());
-            inputs.insert("offset".to_string(), Tensor::from_array(offset_input)?.into());
-
-            let outputs = match decoder_session.run(inputs) {
-                Ok(o) => o,
-                Err(e) => return Err(anyhow!("Decoder run failed at step {}: {}", i, e)),
-            };
+        Ok(Self { encoder, decoder, has_cross_qk, dims })
+    }
+
+    /// Dimensions and special tokens read from the ONNX graph.
+    pub fn dims(&self) -> &ModelDims {
+        &self.dims
+    }
+
+    /// Whether the decoder exposes cross-attention, needed for aligned word timings.
+    pub fn supports_alignment(&self) -> bool {
+        self.has_cross_qk
+    }
+
+    pub fn infer(&self, mel: &Array2<f32>) -> Result<DecodingResult> {
+        self.infer_with_options(mel, &DecodingOptions::default())
+    }
+
+    pub fn infer_with_options(&self, mel: &Array2<f32>, options: &DecodingOptions) -> Result<DecodingResult> {
+        self.infer_with_prompt(mel, options, &[])
+    }
+
+    /// Decode with `prompt` tokens after `<|startofprev|>` as previous context.
+    /// Only the last `n_ctx / 2 - 1` prompt tokens are kept (223 for Whisper's 448-token context).
+    pub fn infer_with_prompt(&self, mel: &Array2<f32>, options: &DecodingOptions, prompt: &[i64]) -> Result<DecodingResult> {
+        let encoded = self.encode(mel)?;
+        self.decode(&encoded, options, prompt)
+    }
+
+    /// Encode and decode several windows at once, which is faster than one by one
+    /// for many short clips.
+    pub fn infer_batch(&self, mels: &[Array2<f32>]) -> Result<Vec<DecodingResult>> {
+        self.infer_batch_with_options(mels, &DecodingOptions::default())
+    }
+
+    pub fn infer_batch_with_options(&self, mels: &[Array2<f32>], options: &DecodingOptions) -> Result<Vec<DecodingResult>> {
+        if mels.is_empty() {
+            return Ok(Vec::new());
+        }
+        let encoded = self.encode_batch(mels)?;
+        self.decode_batch(&encoded, options, &[])
+    }
+
+    /// Decode an encoded window once, at `options.temperature`.
+    /// Only the first window of a batched `encoded` is decoded.
+    pub fn decode(&self, encoded: &EncoderOutput, options: &DecodingOptions, prompt: &[i64]) -> Result<DecodingResult> {
+        let first = if encoded.batch_size() == 1 { None } else { Some(encoded.item(0)?) };
+        Ok(self.decode_batch(first.as_ref().unwrap_or(encoded), options, prompt)?.remove(0))
+    }
+
+    /// Decode every window of an encoded batch once, at `options.temperature`.
+    ///
+    /// Greedy decoding and sampling run the whole batch through the decoder at each
+    /// step, until every sequence has reached EOT. Beam search decodes the windows
+    /// one after another.
+    pub fn decode_batch(&self, encoded: &EncoderOutput, options: &DecodingOptions, prompt: &[i64]) -> Result<Vec<DecodingResult>> {
+        let EncoderOutput { cross_k, cross_v } = encoded;
+        let batch = encoded.batch_size();
+
+        let mut decoder_session = self.decoder.get()?;
+
+        let options = &DecodingOptions {
+            timestamps: options.timestamps || options.word_timestamps,
+            ..options.clone()
+        };
+
+        let dims = &self.dims;
+        let special = &dims.tokens;
+        let languages = match &options.language {
+            // `.en` models have no language token
+            _ if !special.multilingual => vec![special.first_language; batch],
+            Some(language) => vec![special.language_token(language)?; batch],
+            None => rank_languages(&mut decoder_session, dims, cross_k, cross_v, batch)?
+                .iter()
+                .map(|ranked| special.language_token(&ranked[0].0))
+                .collect::<Result<_>>()?,
+        };
+        // Same length for every window, since only the language token differs
+        let sot_sequences: Vec<Vec<i64>> = languages
+            .into_iter()
+            .map(|language| special.sot_sequence(language, options, prompt, dims.max_prompt_len()))
+            .collect();
+
+        let searched = match options.beam_size {
+            Some(beam_size) if beam_size > 1 && options.temperature == 0.0 => sot_sequences
+                .iter()
+                .enumerate()
+                .map(|(i, sot_sequence)| {
+                    let item = if batch == 1 { None } else { Some(encoded.item(i)?) };
+                    let EncoderOutput { cross_k, cross_v } = item.as_ref().unwrap_or(encoded);
+                    beam_search(&mut decoder_session, dims, cross_k, cross_v, sot_sequence, beam_size, options)
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => greedy_search(&mut decoder_session, dims, cross_k, cross_v, &sot_sequences, options)?,
+        };
+
+        Ok(searched
+            .into_iter()
+            .zip(&sot_sequences)
+            .map(|(Searched { tokens, logprobs, sum_logprob, no_speech_prob }, sot_sequence)| {
+                let sampled_len = tokens.len() - sot_sequence.len();
+                let mut token_logprobs = vec![0.0; sot_sequence.len()];
+                token_logprobs.extend(logprobs);
+                DecodingResult {
+                    tokens,
+                    token_logprobs,
+                    avg_logprob: sum_logprob / (sampled_len + 1) as f32,
+                    temperature: options.temperature,
+                    no_speech_prob,
+                }
+            })
+            .collect())
+    }
+
+    /// Detect the spoken language from one decoder step after the encoder.
+    /// Returns every language with its probability, most likely first.
+    /// `.en` models always return English.
+    pub fn detect_language(&self, mel: &Array2<f32>) -> Result<Vec<(String, f32)>> {
+        if !self.dims.tokens.multilingual {
+            return Ok(vec![("en".to_string(), 1.0)]);
+        }
+        let encoded = self.encode(mel)?;
+        Ok(self.detect_languages(&encoded)?.remove(0))
+    }
+
+    /// Same as `detect_language`, for each window of an already encoded batch.
+    pub fn detect_languages(&self, encoded: &EncoderOutput) -> Result<Vec<Vec<(String, f32)>>> {
+        let batch = encoded.batch_size();
+        if !self.dims.tokens.multilingual {
+            return Ok(vec![vec![("en".to_string(), 1.0)]; batch]);
+        }
+
+        let mut decoder_session = self.decoder.get()?;
+        rank_languages(&mut decoder_session, &self.dims, &encoded.cross_k, &encoded.cross_v, batch)
+    }
+
+    /// Align text tokens to the audio using cross-attention and DTW.
+    ///
+    /// `prefix` is the SOT sequence of the decoded window, without the prompt. Returns the
+    /// token boundaries in seconds (see `alignment::token_boundaries`), or `None` when
+    /// the decoder does not expose cross-attention or the tokens do not fit its context.
+    pub fn align(&self, encoded: &EncoderOutput, prefix: &[i64], text_tokens: &[i64], duration: f32) -> Result<Option<Vec<f32>>> {
+        if !self.has_cross_qk || text_tokens.is_empty() {
+            return Ok(None);
+        }
+
+        let mut tokens = prefix.to_vec();
+        tokens.push(self.dims.tokens.no_timestamps);
+        tokens.extend_from_slice(text_tokens);
+        tokens.push(self.dims.tokens.eot);
+        // A window decoded up to the context length leaves no room for the extra tokens
+        if tokens.len() > self.dims.n_ctx {
+            return Ok(None);
+        }
+
+        let mut decoder_session = self.decoder.get()?;
+        let cache = KvCache::new(&self.dims, 1)?;
+        let tokens = Array2::from_shape_vec((1, tokens.len()), tokens)?;
+        let outputs = run_decoder(&mut decoder_session, tokens, 0, &cache, &encoded.cross_k, &encoded.cross_v)?;
+
+        let (shape, data) = outputs[CROSS_QK_OUTPUT].try_extract_tensor::<f32>()?;
+        let shape_vec: Vec<usize> = shape.iter().map(|&x| x as usize).collect();
+        let qk = ndarray::ArrayView5::from_shape(
+            (shape_vec[0], shape_vec[1], shape_vec[2], shape_vec[3], shape_vec[4]),
+            data
+        )?;
+
+        // Use every head of the second half of the layers, from the <|notimestamps|>
+        // position up to the last text token
+        let first = prefix.len();
+        let last = first + text_tokens.len();
+        let heads = qk.slice(ndarray::s![shape_vec[0] / 2.., 0, .., first..=last, ..]);
+        let (layers, n_head, positions, frames) = heads.dim();
+        let heads = heads.to_owned().into_shape_with_order((layers * n_head, positions, frames))?;
+
+        let num_frames = (duration / crate::transcript::TIME_PRECISION).round() as usize;
+        Ok(Some(crate::alignment::token_boundaries(&heads, num_frames)))
+    }
+
+    /// Run the encoder on the mel spectrogram of one window.
+    pub fn encode(&self, mel: &Array2<f32>) -> Result<EncoderOutput> {
+        self.encode_batch(std::slice::from_ref(mel))
+    }
+
+    /// Run the encoder on the mel spectrograms of several windows of the same shape.
+    pub fn encode_batch(&self, mels: &[Array2<f32>]) -> Result<EncoderOutput> {
+        let views: Vec<_> = mels.iter().map(|mel| mel.view()).collect();
+        let batch_mel = ndarray::stack(Axis(0), &views)?;
+
+        let inputs = ort::inputs![
+            "mel" => Tensor::from_array(batch_mel)?,
+        ];
+
+        let mut encoder_session = self.encoder.get()?;
+        let mut encoder_out = encoder_session.run(inputs).map_err(BreezeError::Encoder)?;
+
+        // Keep the output values as they are, the decoder only reads them
+        let mut take = |name: &str| -> Result<Tensor<f32>> {
+            let value = encoder_out
+                .remove(name)
+                .ok_or_else(|| BreezeError::UnsupportedModel(format!("encoder output '{}' is missing", name)))?;
+            Ok(value.downcast::<TensorValueType<f32>>()?)
+        };
+        let cross_k = take("n_layer_cross_k")?;
+        let cross_v = take("n_layer_cross_v")?;
+
+        Ok(EncoderOutput { cross_k, cross_v })
+    }
+}
+
+/// Run the decoder on `tokens`, shape `[batch, n_tokens]`, starting at `offset`.
+fn run_decoder<'s>(
+    session: &'s mut Session,
+    tokens: Array2<i64>,
+    offset: usize,
+    cache: &KvCache,
+    cross_k: &Tensor<f32>,
+    cross_v: &Tensor<f32>,
+) -> Result<SessionOutputs<'s>> {
+    let offset_input = Array1::from_shape_vec((1,), vec![offset as i64])?;
+
+    let mut inputs: HashMap<String, SessionInputValue<'_>> = HashMap::new();
+    inputs.insert("tokens".to_string(), Tensor::from_array(tokens)?.into());
+    // Caches and cross-attention values are passed as views, without copying
+    inputs.insert("in_n_layer_self_k_cache".to_string(), (&cache.k).into());
+    inputs.insert("in_n_layer_self_v_cache".to_string(), (&cache.v).into());
+    inputs.insert("n_layer_cross_k".to_string(), cross_k.into());
+    inputs.insert("n_layer_cross_v".to_string(), cross_v.into());
+    inputs.insert("offset".to_string(), Tensor::from_array(offset_input)?.into());
+
+    session
+        .run(inputs)
+        .map_err(|source| BreezeError::Decoder { step: offset, source })
+}
+
+/// Run the decoder on the tokens of one sequence starting at `offset`, updating `cache` in place.
+/// Returns the logits of every token, shape `[n_tokens, vocab]`.
+fn decode_step(
+    session: &mut Session,
+    tokens: &[i64],
+    offset: usize,
+    cache: &mut KvCache,
+    cross_k: &Tensor<f32>,
+    cross_v: &Tensor<f32>,
+) -> Result<Array2<f32>> {
+    let tokens = Array2::from_shape_vec((1, tokens.len()), tokens.to_vec())?;
+    let logits = decode_batch_step(session, tokens, offset, cache, cross_k, cross_v)?;
+    Ok(logits.index_axis_move(Axis(0), 0))
+}
+
+/// Batched `decode_step`, with `tokens` of shape `[batch, n_tokens]`.
+/// Returns logits of shape `[batch, n_tokens, vocab]`.
+fn decode_batch_step(
+    session: &mut Session,
+    tokens: Array2<i64>,
+    offset: usize,
+    cache: &mut KvCache,
+    cross_k: &Tensor<f32>,
+    cross_v: &Tensor<f32>,
+) -> Result<Array3<f32>> {
+    let mut outputs = run_decoder(session, tokens, offset, cache, cross_k, cross_v)?;
 
-            // Process outputs
-            {
-                let (shape, data) = outputs["out_n_layer_self_k_cache"].try_extract_tensor::<f32>()?;
-                // Should match [32, 1, 448, 1280]
-                let shape_vec: Vec<usize> = shape.iter().map(|&x| x as usize).collect();
-                let out_arr = Array4::from_shape_vec(
-                    (shape_vec[0], shape_vec[1], shape_vec[2], shape_vec[3]),
-                    data.to_vec()
-                )?;
-                self_k_cache.assign(&out_arr);
+    // Only this (small) output is copied
+    let logits = outputs["logits"]
+        .try_extract_array::<f32>()?
+        .into_dimensionality::<Ix3>()?
+        .to_owned();
+
+    // The output caches become the inputs of the next step
+    let mut take = |name: &str| {
+        outputs
+            .remove(name)
+            .ok_or_else(|| BreezeError::UnsupportedModel(format!("decoder output '{}' is missing", name)))
+    };
+    cache.k = take("out_n_layer_self_k_cache")?;
+    cache.v = take("out_n_layer_self_v_cache")?;
+
+    Ok(logits)
+}
+
+/// Logits of the last token of a decoder step.
+fn last_logits(logits: &Array2<f32>) -> Vec<f32> {
+    logits.row(logits.nrows() - 1).to_vec()
+}
+
+/// Probability of `<|nospeech|>` at the `SOT` position, from the first decoder step
+/// over the whole SOT sequence.
+fn no_speech_probability(logits: ArrayView2<f32>, special: &SpecialTokens, sot_sequence: &[i64]) -> f32 {
+    let sot_index = sot_sequence.iter().position(|&t| t == special.sot).unwrap_or(0);
+    let row = logits.row(sot_index).to_vec();
+    log_softmax(&row)[special.no_speech as usize].exp()
+}
+
+/// Language probabilities of each of `batch` windows from the logits after `SOT`,
+/// most likely first.
+fn rank_languages(
+    session: &mut Session,
+    dims: &ModelDims,
+    cross_k: &Tensor<f32>,
+    cross_v: &Tensor<f32>,
+    batch: usize,
+) -> Result<Vec<Vec<(String, f32)>>> {
+    let special = &dims.tokens;
+    let mut cache = KvCache::new(dims, batch)?;
+    let tokens = Array2::from_elem((batch, 1), special.sot);
+    let logits = decode_batch_step(session, tokens, 0, &mut cache, cross_k, cross_v)?;
+
+    let first = special.first_language as usize;
+    Ok(logits
+        .outer_iter()
+        .map(|logits| {
+            // Softmax over the language tokens only
+            let logits = logits.row(logits.nrows() - 1).to_vec();
+            let language_logits = &logits[first..first + special.n_languages];
+            let probs = log_softmax(language_logits).into_iter().map(f32::exp);
+
+            let mut ranked: Vec<(String, f32)> = special
+                .languages()
+                .iter()
+                .map(|code| code.to_string())
+                .zip(probs)
+                .collect();
+            ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+            ranked
+        })
+        .collect())
+}
+
+/// Greedy decoding, or sampling when `options.temperature` is above 0, of a batch of
+/// windows in lockstep. All SOT sequences must have the same length.
+/// Sequences that reached EOT are fed EOT until every sequence has ended.
+fn greedy_search(
+    session: &mut Session,
+    dims: &ModelDims,
+    cross_k: &Tensor<f32>,
+    cross_v: &Tensor<f32>,
+    sot_sequences: &[Vec<i64>],
+    options: &DecodingOptions,
+) -> Result<Vec<Searched>> {
+    let batch = sot_sequences.len();
+    let special = &dims.tokens;
+    let mut searched: Vec<Searched> = sot_sequences
+        .iter()
+        .map(|sot_sequence| Searched {
+            tokens: sot_sequence.clone(),
+            logprobs: Vec::new(),
+            sum_logprob: 0.0,
+            no_speech_prob: 0.0,
+        })
+        .collect();
+    let mut finished = vec![false; batch];
+    let mut cache = KvCache::new(dims, batch)?;
+    let mut input = Array2::from_shape_vec((batch, sot_sequences[0].len()), sot_sequences.concat())?;
+    // Number of tokens already in the KV cache, and in every (padded) sequence
+    let mut fed = 0;
+    let mut len = input.ncols();
+    let mut rng = rand::rng();
+
+    while len < dims.n_ctx && finished.contains(&false) {
+        let step_logits = decode_batch_step(session, input, fed, &mut cache, cross_k, cross_v)?;
+        let mut next = Vec::with_capacity(batch);
+        for (i, sequence) in searched.iter_mut().enumerate() {
+            let step_logits = step_logits.index_axis(Axis(0), i);
+            if fed == 0 {
+                sequence.no_speech_prob = no_speech_probability(step_logits, special, &sot_sequences[i]);
             }
-            {
-                let (shape, data) = outputs["out_n_layer_self_v_cache"].try_extract_tensor::<f32>()?;
-                let shape_vec: Vec<usize> = shape.iter().map(|&x| x as usize).collect();
-                let out_arr = Array4::from_shape_vec(
-                    (shape_vec[0], shape_vec[1], shape_vec[2], shape_vec[3]),
-                    data.to_vec()
-                )?;
-                self_v_cache.assign(&out_arr);
+            if finished[i] {
+                next.push(special.eot);
+                continue;
             }
 
-            let next_token = {
-                let (shape, data) = outputs["logits"].try_extract_tensor::<f32>()?;
-                // Shape [1, 1, Vocab]
-                let shape_vec: Vec<usize> = shape.iter().map(|&x| x as usize).collect();
-                let logits_arr = Array3::from_shape_vec(
-                    (shape_vec[0], shape_vec[1], shape_vec[2]),
-                    data.to_vec()
-                )?;
-                
-                let logits_slice = logits_arr.slice(ndarray::s![0, 0, ..]);
-                let (token, _) = logits_slice.iter().enumerate().fold(
-                    (0, f32::NEG_INFINITY), 
+            let mut logits = step_logits.row(step_logits.nrows() - 1).to_vec();
+            if options.timestamps {
+                special.apply_timestamp_rules(&mut logits, &sequence.tokens);
+            }
+
+            let token = if options.temperature > 0.0 {
+                sample(&logits, options.temperature, &mut rng)
+            } else {
+                let (token, _) = logits.iter().enumerate().fold(
+                    (0, f32::NEG_INFINITY),
                     |(argmax, max), (i, &val)| if val > max { (i, val) } else { (argmax, max) }
                 );
-                token as i64
+                token
             };
+            let logprob = log_softmax(&logits)[token];
+            sequence.sum_logprob += logprob;
+            let next_token = token as i64;
+
+            if next_token == special.eot {
+                finished[i] = true;
+            } else {
+                sequence.tokens.push(next_token);
+                sequence.logprobs.push(logprob);
+            }
+            next.push(next_token);
+        }
+        fed = len;
+        len += 1;
+        input = Array2::from_shape_vec((batch, 1), next)?;
+    }
+
+    Ok(searched)
+}
+
+/// Sample a token from `softmax(logits / temperature)`.
+fn sample(logits: &[f32], temperature: f32, rng: &mut impl Rng) -> usize {
+    let max = logits.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+    let weights: Vec<f32> = logits.iter().map(|&x| ((x - max) / temperature).exp()).collect();
+    let total: f32 = weights.iter().sum();
+
+    let mut target = rng.random::<f32>() * total;
+    for (i, &w) in weights.iter().enumerate() {
+        if target < w {
+            return i;
+        }
+        target -= w;
+    }
+    // Rounding left us past the end, take the last possible token
+    weights.iter().rposition(|&w| w > 0.0).unwrap_or(0)
+}
+
+/// Beam search as in the reference Whisper implementation.
+/// Every beam keeps its own self-attention KV cache, which is forked when a beam
+/// has several surviving children.
+fn beam_search(
+    session: &mut Session,
+    dims: &ModelDims,
+    cross_k: &Tensor<f32>,
+    cross_v: &Tensor<f32>,
+    sot_sequence: &[i64],
+    beam_size: usize,
+    options: &DecodingOptions,
+) -> Result<Searched> {
+    let max_candidates = max_candidates(beam_size, options.patience);
+    let special = &dims.tokens;
 
-            if next_token == EOT {
-                break;
+    let mut beams = vec![Beam {
+        tokens: sot_sequence.to_vec(),
+        logprobs: Vec::new(),
+        sum_logprob: 0.0,
+        cache: KvCache::new(dims, 1)?,
+    }];
+    // `no_speech_prob` is filled in once a winner is picked
+    let mut finished: Vec<Searched> = Vec::new();
+    // All beams have the same length, and the same number of tokens in their cache
+    let mut fed = 0;
+    let mut no_speech_prob = 0.0;
+
+    while beams[0].tokens.len() < dims.n_ctx {
+        let mut candidates = Vec::new();
+        for (b, beam) in beams.iter_mut().enumerate() {
+            let step_logits = decode_step(session, &beam.tokens[fed..], fed, &mut beam.cache, cross_k, cross_v)?;
+            if fed == 0 {
+                no_speech_prob = no_speech_probability(step_logits.view(), special, sot_sequence);
+            }
+            let mut logits = last_logits(&step_logits);
+            if options.timestamps {
+                special.apply_timestamp_rules(&mut logits, &beam.tokens);
+            }
+            let logprobs = log_softmax(&logits);
+
+            let mut top: Vec<(usize, f32)> = logprobs.iter().copied().enumerate().collect();
+            let k = (beam_size + 1).min(top.len());
+            top.select_nth_unstable_by(k - 1, |a, b| b.1.total_cmp(&a.1));
+            for &(token, logprob) in &top[..k] {
+                candidates.push(BeamCandidate {
+                    parent: b,
+                    token: token as i64,
+                    logprob,
+                    sum_logprob: beam.sum_logprob + logprob,
+                });
             }
-            tokens.push(next_token);
         }
+        fed = beams[0].tokens.len();
 
-        Ok(tokens)
+        let (selected, ended) = select_candidates(candidates, special.eot, beam_size, max_candidates - finished.len());
+        finished.extend(ended.into_iter().map(|c| Searched {
+            tokens: beams[c.parent].tokens.clone(),
+            logprobs: beams[c.parent].logprobs.clone(),
+            sum_logprob: c.sum_logprob,
+            no_speech_prob: 0.0,
+        }));
+
+        if finished.len() >= max_candidates || selected.is_empty() {
+            break;
+        }
+
+        // Fork caches: the last child of a beam takes it over, earlier ones clone it.
+        let mut children_left = vec![0usize; beams.len()];
+        for candidate in &selected {
+            children_left[candidate.parent] += 1;
+        }
+        let mut parents: Vec<Option<Beam>> = beams.into_iter().map(Some).collect();
+        beams = Vec::with_capacity(selected.len());
+        for BeamCandidate { parent, token, logprob, sum_logprob } in selected {
+            children_left[parent] -= 1;
+            let (mut tokens, mut logprobs, cache) = if children_left[parent] == 0 {
+                let beam = parents[parent].take().unwrap();
+                (beam.tokens, beam.logprobs, beam.cache)
+            } else {
+                let beam = parents[parent].as_ref().unwrap();
+                (beam.tokens.clone(), beam.logprobs.clone(), beam.cache.fork()?)
+            };
+            tokens.push(token);
+            logprobs.push(logprob);
+            beams.push(Beam { tokens, logprobs, sum_logprob, cache });
+        }
+    }
+
+    // Sequences that never reached EOT are still candidates
+    if finished.is_empty() {
+        finished = beams
+            .into_iter()
+            .map(|b| Searched { tokens: b.tokens, logprobs: b.logprobs, sum_logprob: b.sum_logprob, no_speech_prob: 0.0 })
+            .collect();
     }
+
+    let score = |Searched { tokens, sum_logprob, .. }: &Searched| {
+        sequence_score(*sum_logprob, tokens.len() - sot_sequence.len(), options.length_penalty)
+    };
+
+    let best = finished
+        .into_iter()
+        .max_by(|a, b| score(a).total_cmp(&score(b)))
+        .ok_or_else(|| BreezeError::InvalidConfig("beam search produced no candidates".to_string()))?;
+    Ok(Searched { no_speech_prob, ..best })
+}
+
+/// A possible next token of beam `parent`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeamCandidate {
+    pub parent: usize,
+    pub token: i64,
+    /// Log probability of `token`.
+    pub logprob: f32,
+    /// Log probability of the whole beam with `token`.
+    pub sum_logprob: f32,
+}
+
+/// Number of finished sequences beam search collects before it stops:
+/// `beam_size * patience` rounded, and at least one.
+pub fn max_candidates(beam_size: usize, patience: Option<f32>) -> usize {
+    ((beam_size as f32 * patience.unwrap_or(1.0)).round() as usize).max(1)
+}
+
+/// Go through the candidates of one step, most likely first, and return the beams to
+/// continue and those that ended with EOT. At most `beam_size` beams continue, and
+/// EOT candidates past the `finished_room` still left are dropped.
+pub fn select_candidates(
+    mut candidates: Vec<BeamCandidate>,
+    eot: i64,
+    beam_size: usize,
+    finished_room: usize,
+) -> (Vec<BeamCandidate>, Vec<BeamCandidate>) {
+    candidates.sort_by(|a, b| b.sum_logprob.total_cmp(&a.sum_logprob));
+
+    let mut selected = Vec::new();
+    let mut finished = Vec::new();
+    for candidate in candidates {
+        if candidate.token == eot {
+            if finished.len() < finished_room {
+                finished.push(candidate);
+            }
+        } else {
+            selected.push(candidate);
+        }
+        if selected.len() >= beam_size {
+            break;
+        }
+    }
+    (selected, finished)
+}
+
+/// Score that ranks finished beams: the log probability over the number of sampled
+/// tokens, or over `((5 + length) / 6)^alpha` with a length penalty `alpha`.
+pub fn sequence_score(sum_logprob: f32, length: usize, length_penalty: Option<f32>) -> f32 {
+    let length = length as f32;
+    let penalty = match length_penalty {
+        Some(alpha) => ((5.0 + length) / 6.0).powf(alpha),
+        None => length.max(1.0),
+    };
+    sum_logprob / penalty
+}
+
+fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+    let log_sum = logits.iter().map(|&x| (x - max).exp()).sum::<f32>().ln() + max;
+    logits.iter().map(|&x| x - log_sum).collect()
 }
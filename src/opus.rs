@@ -0,0 +1,102 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use audiopus::coder::Decoder as OpusDecoder;
+use audiopus::{Channels, ErrorCode, SampleRate};
+use ogg::reading::{OggReadError, PacketReader};
+
+use crate::error::{BreezeError, Result};
+
+/// Whether the content is an Ogg stream starting with an Opus header. The `OpusHead`
+/// packet is alone on the first page. The reader is rewound to where it was.
+pub(crate) fn is_ogg_opus(reader: &mut (impl Read + Seek)) -> Result<bool> {
+    let start = reader.stream_position()?;
+    let mut page = Vec::with_capacity(27 + 255 + 8);
+    reader.take(27 + 255 + 8).read_to_end(&mut page)?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    if !page.starts_with(b"OggS") || page.len() < 27 {
+        return Ok(false);
+    }
+    let payload = 27 + page[26] as usize;
+    Ok(page.get(payload..payload + 8) == Some(b"OpusHead"))
+}
+
+/// Decode Opus in Ogg (RFC 7845) with libopus, straight to 16kHz.
+/// Only the channel mapping for mono and stereo is supported.
+pub(crate) fn decode(source: impl Read + Seek) -> Result<(Vec<Vec<f32>>, usize)> {
+    // Granule positions and the pre-skip count samples at 48kHz
+    const RATE_DIVISOR: usize = 48000 / crate::audio::SAMPLE_RATE;
+    // The longest Opus packet is 120ms
+    const MAX_PACKET_SAMPLES: usize = crate::audio::SAMPLE_RATE * 120 / 1000;
+
+    let mut reader = PacketReader::new(source);
+    let head = reader.read_packet_expected()?;
+    let header = &head.data;
+    if header.len() < 19 {
+        return Err(BreezeError::AudioDecode("truncated Opus header".to_string()));
+    }
+    let channels = header[9] as usize;
+    let pre_skip = u16::from_le_bytes([header[10], header[11]]) as usize / RATE_DIVISOR;
+    let output_gain = i16::from_le_bytes([header[16], header[17]]);
+    let opus_channels = match (header[18], channels) {
+        (0, 1) => Channels::Mono,
+        (0, 2) => Channels::Stereo,
+        (family, _) => {
+            return Err(BreezeError::UnsupportedAudioFormat(format!(
+                "Opus with {} channels in mapping family {}",
+                channels, family
+            )))
+        }
+    };
+
+    let mut decoder = OpusDecoder::new(SampleRate::Hz16000, opus_channels)?;
+    decoder.set_gain(output_gain as i32)?;
+    let serial = head.stream_serial();
+    let mut buffer = vec![0.0f32; MAX_PACKET_SAMPLES * channels];
+    let mut samples = Vec::new();
+    let mut end = None;
+    let mut comments_read = false;
+    while let Some(packet) = reader.read_packet()? {
+        if packet.stream_serial() != serial {
+            continue;
+        }
+        // The packet after the header holds the comments
+        if !comments_read {
+            comments_read = true;
+            continue;
+        }
+
+        let decoded = match decoder.decode_float(Some((&packet.data).try_into()?), (&mut buffer).try_into()?, false) {
+            Ok(decoded) => decoded,
+            // A corrupt packet only loses its own samples
+            Err(audiopus::Error::Opus(ErrorCode::InvalidPacket)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        samples.extend_from_slice(&buffer[..decoded * channels]);
+        if packet.last_in_stream() {
+            end = Some(packet.absgp_page() as usize / RATE_DIVISOR);
+        }
+    }
+
+    // The last page tells how many samples are real, the rest is padding
+    if let Some(end) = end {
+        samples.truncate(end * channels);
+    }
+    samples.drain(..(pre_skip * channels).min(samples.len()));
+    Ok((crate::audio::deinterleave(&samples, channels), crate::audio::SAMPLE_RATE))
+}
+
+impl From<OggReadError> for BreezeError {
+    fn from(e: OggReadError) -> Self {
+        match e {
+            OggReadError::ReadError(e) => Self::Io(e),
+            e => Self::AudioDecode(e.to_string()),
+        }
+    }
+}
+
+impl From<audiopus::Error> for BreezeError {
+    fn from(e: audiopus::Error) -> Self {
+        Self::AudioDecode(format!("Opus: {}", e))
+    }
+}
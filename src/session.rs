@@ -0,0 +1,242 @@
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, PoisonError};
+use ort::{
+    execution_providers::CPUExecutionProvider,
+    session::{Session, builder::{GraphOptimizationLevel, PrepackedWeights}},
+};
+
+use crate::error::{BreezeError, Result};
+
+pub use ort::execution_providers::ExecutionProviderDispatch;
+
+/// How much ONNX Runtime rewrites the graph when loading a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationLevel {
+    Disable,
+    /// Removes redundant nodes and computation.
+    Basic,
+    /// Adds node fusions.
+    Extended,
+    /// Adds layout optimizations, which tie the optimized graph to the CPU it was made on.
+    #[default]
+    All,
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+            OptimizationLevel::All => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+/// ONNX Runtime settings shared by the encoder and decoder sessions.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    /// Threads used within an operator. `None` lets ONNX Runtime pick one per core.
+    pub intra_threads: Option<usize>,
+    /// Threads used to run independent operators in parallel. `None` runs them in sequence.
+    pub inter_threads: Option<usize>,
+    pub optimization_level: OptimizationLevel,
+    /// Keep freed CPU memory in an arena for reuse. Faster, but memory is not given back.
+    pub memory_arena: bool,
+    /// Execution providers to try in order, e.g. CUDA. The CPU is always the last fallback.
+    pub execution_providers: Vec<ExecutionProviderDispatch>,
+    /// Directory where optimized models are saved on first load and loaded from afterwards,
+    /// which skips the optimization step. A model file that changes, or a different
+    /// `optimization_level` or list of execution providers, gets its own copy; delete
+    /// the directory when changing hardware.
+    pub optimized_model_dir: Option<PathBuf>,
+    /// Number of sessions per model, i.e. how many threads can run it at once.
+    /// Prepacked weights are shared, but each session otherwise holds its own copy
+    /// of the model, so memory grows with the pool.
+    pub pool_size: usize,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            intra_threads: Some(4),
+            inter_threads: None,
+            optimization_level: OptimizationLevel::default(),
+            memory_arena: true,
+            execution_providers: Vec::new(),
+            optimized_model_dir: None,
+            pool_size: 1,
+        }
+    }
+}
+
+impl SessionOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.pool_size == 0 {
+            return Err(BreezeError::InvalidConfig("session pool size must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Create `pool_size` sessions for the model at `path`.
+    pub(crate) fn commit_pool(&self, path: &Path, name: &'static str) -> Result<SessionPool> {
+        self.validate()?;
+        check_exists(path)?;
+        let prepacked = PrepackedWeights::new();
+        let sessions = (0..self.pool_size)
+            .map(|_| self.commit_with(path, Some(&prepacked)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SessionPool { name, idle: Mutex::new(sessions), returned: Condvar::new() })
+    }
+
+    fn commit_with(&self, path: &Path, prepacked: Option<&PrepackedWeights>) -> Result<Session> {
+        let load_error = |source| BreezeError::ModelLoad { path: path.to_path_buf(), source };
+
+        let mut builder = Session::builder()?;
+        if let Some(threads) = self.intra_threads {
+            builder = builder.with_intra_threads(threads)?;
+        }
+        if let Some(threads) = self.inter_threads {
+            builder = builder.with_parallel_execution(true)?.with_inter_threads(threads)?;
+        }
+
+        let mut providers = self.execution_providers.clone();
+        providers.push(CPUExecutionProvider::default().with_arena_allocator(self.memory_arena).build());
+        builder = builder.with_execution_providers(providers)?;
+        if let Some(prepacked) = prepacked {
+            builder = builder.with_prepacked_weights(prepacked)?;
+        }
+
+        let Some(dir) = &self.optimized_model_dir else {
+            return builder
+                .with_optimization_level(self.optimization_level.into())?
+                .commit_from_file(path)
+                .map_err(load_error);
+        };
+
+        let name = self.cache_name(path)?;
+        let cached = dir.join(format!("{}.optimized.onnx", name));
+        if cached.exists() {
+            return builder
+                .with_optimization_level(GraphOptimizationLevel::Disable)?
+                .commit_from_file(&cached)
+                .map_err(load_error);
+        }
+
+        std::fs::create_dir_all(dir)?;
+        // Whisper weights exceed the 2GB protobuf limit, so they go to a separate file
+        builder
+            .with_optimization_level(self.optimization_level.into())?
+            .with_optimized_model_path(&cached)?
+            .with_config_entry(
+                "session.optimized_model_external_initializers_file_name",
+                format!("{}.optimized.weights", name),
+            )?
+            .commit_from_file(path)
+            .map_err(load_error)
+    }
+
+    /// Name of the optimized copy of the model at `path`. It includes a fingerprint of
+    /// the source file, the optimization level and the execution providers, so a replaced
+    /// model or changed settings are optimized again instead of loading a stale copy.
+    fn cache_name(&self, path: &Path) -> Result<String> {
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| BreezeError::InvalidConfig(format!("invalid model path '{}'", path.display())))?;
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        let mut hash = Fnv1a::default();
+        hash.write(std::fs::canonicalize(path)?.as_os_str().as_encoded_bytes());
+        hash.write(&metadata.len().to_le_bytes());
+        hash.write(&modified.as_nanos().to_le_bytes());
+        hash.write(format!("{:?}", self.optimization_level).as_bytes());
+        for provider in &self.execution_providers {
+            // The debug output starts with the provider's name
+            hash.write(format!("{:?}", provider).as_bytes());
+        }
+        Ok(format!("{}-{:016x}", stem, hash.0))
+    }
+}
+
+/// 64-bit FNV-1a. Unlike std's `DefaultHasher`, it gives the same file names
+/// after a Rust upgrade.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    /// Add a field. Its length goes first, so fields can't run into each other.
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+fn check_exists(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Err(BreezeError::ModelNotFound(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Sessions of one model, lent to one thread at a time.
+pub(crate) struct SessionPool {
+    name: &'static str,
+    idle: Mutex<Vec<Session>>,
+    returned: Condvar,
+}
+
+impl SessionPool {
+    /// Take an idle session, waiting for one if all are in use.
+    pub(crate) fn get(&self) -> Result<PooledSession<'_>> {
+        let idle = self.idle.lock().map_err(|_| BreezeError::Poisoned(self.name))?;
+        let mut idle = self
+            .returned
+            .wait_while(idle, |idle| idle.is_empty())
+            .map_err(|_| BreezeError::Poisoned(self.name))?;
+        let session = idle.pop().expect("waited for an idle session");
+        Ok(PooledSession { pool: self, session: Some(session) })
+    }
+}
+
+/// A session taken from a `SessionPool`, given back when dropped.
+pub(crate) struct PooledSession<'a> {
+    pool: &'a SessionPool,
+    session: Option<Session>,
+}
+
+impl Deref for PooledSession<'_> {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        self.session.as_ref().expect("session is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledSession<'_> {
+    fn deref_mut(&mut self) -> &mut Session {
+        self.session.as_mut().expect("session is only taken on drop")
+    }
+}
+
+impl Drop for PooledSession<'_> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            // The lock is never held across a panic, but don't lose the session if it was
+            self.pool.idle.lock().unwrap_or_else(PoisonError::into_inner).push(session);
+            self.pool.returned.notify_one();
+        }
+    }
+}
@@ -1,35 +1,133 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use anyhow::Result;
+use std::ops::Range;
+use std::path::Path;
 use base64::{Engine as _, engine::general_purpose};
+use regex::Regex;
+
+use crate::error::{BreezeError, Result};
+
+// Whisper's (GPT-2) pre-tokenization pattern, minus the `\s+(?!\S)` lookahead
+// which the regex crate does not support; see `split_pieces`.
+const PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+";
 
 pub struct Tokenizer {
     id_to_bytes: HashMap<i64, Vec<u8>>,
+    /// Token bytes to id. Ids are the BPE merge ranks.
+    bytes_to_id: HashMap<Vec<u8>, i64>,
+    pattern: Regex,
+}
+
+/// A word and the range of token indices it was decoded from.
+/// CJK characters are words of their own; several of them may share the same tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordTokens {
+    pub text: String,
+    pub tokens: Range<usize>,
 }
 
 impl Tokenizer {
     pub fn new(path: &str) -> Result<Self> {
+        if !Path::new(path).exists() {
+            return Err(BreezeError::ModelNotFound(path.into()));
+        }
         let file = File::open(path)?;
         let reader = BufReader::new(file);
         let mut id_to_bytes = HashMap::new();
 
         for (i, line) in reader.lines().enumerate() {
             let line = line?;
-            // Format is: BASE64_TOKEN NUMBER
+            // Format is: BASE64_TOKEN RANK
             // Example: IQ== 0
-            
+            // The rank is the token id; files without it use the line number.
+
             let parts: Vec<&str> = line.split_whitespace().collect();
             if parts.is_empty() {
                 continue;
             }
             let raw_token = parts[0];
+            let rank = match parts.get(1) {
+                Some(rank) => rank.parse::<i64>().map_err(|e| BreezeError::TokenizerParse {
+                    line: i + 1,
+                    message: format!("invalid rank '{}': {}", rank, e),
+                })?,
+                None => i as i64,
+            };
 
             let decoded_bytes = decode_token_bytes(raw_token);
-            id_to_bytes.insert(i as i64, decoded_bytes);
+            id_to_bytes.insert(rank, decoded_bytes);
+        }
+
+        let bytes_to_id: HashMap<Vec<u8>, i64> = id_to_bytes
+            .iter()
+            .map(|(&id, bytes)| (bytes.clone(), id))
+            .collect();
+        let pattern = Regex::new(PATTERN).expect("pre-tokenization pattern is valid");
+
+        Ok(Self { id_to_bytes, bytes_to_id, pattern })
+    }
+
+    /// Encode text into token ids with tiktoken-style byte-level BPE.
+    /// Bytes that are not in the vocabulary are skipped.
+    pub fn encode(&self, text: &str) -> Vec<i64> {
+        let mut ids = Vec::new();
+        for piece in self.split_pieces(text) {
+            match self.bytes_to_id.get(piece.as_bytes()) {
+                Some(&id) => ids.push(id),
+                None => ids.extend(self.byte_pair_merge(piece.as_bytes())),
+            }
+        }
+        ids
+    }
+
+    /// Pre-tokenize like Whisper. A run of whitespace followed by other text leaves
+    /// its last character to the next piece, emulating `\s+(?!\S)`.
+    fn split_pieces<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut pieces = Vec::new();
+        let mut pos = 0;
+
+        while let Some(m) = self.pattern.find_at(text, pos) {
+            let mut end = m.end();
+            let piece = m.as_str();
+            if end < text.len() && piece.chars().all(char::is_whitespace) && piece.chars().count() > 1 {
+                end -= piece.chars().last().map_or(0, char::len_utf8);
+            }
+            pieces.push(&text[m.start()..end]);
+            pos = end;
+        }
+
+        pieces
+    }
+
+    /// Merge the bytes of one piece, always joining the adjacent pair with the lowest rank.
+    fn byte_pair_merge(&self, piece: &[u8]) -> Vec<i64> {
+        let rank = |parts: &[usize], i: usize| -> Option<i64> {
+            if i + 2 < parts.len() {
+                self.bytes_to_id.get(&piece[parts[i]..parts[i + 2]]).copied()
+            } else {
+                None
+            }
+        };
+
+        // Boundaries between the current parts
+        let mut parts: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = (0..parts.len().saturating_sub(2))
+                .filter_map(|i| rank(&parts, i).map(|r| (r, i)))
+                .min();
+            match best {
+                Some((_, i)) => {
+                    parts.remove(i + 1);
+                }
+                None => break,
+            }
         }
 
-        Ok(Self { id_to_bytes })
+        parts
+            .windows(2)
+            .filter_map(|w| self.bytes_to_id.get(&piece[w[0]..w[1]]).copied())
+            .collect()
     }
 
     pub fn decode(&self, ids: &[i64]) -> String {
@@ -62,6 +160,97 @@ impl Tokenizer {
         }
         String::from_utf8_lossy(&all_bytes).into_owned()
     }
+
+    /// Split text tokens into words.
+    ///
+    /// Tokens are first joined until they form valid UTF-8. A new word starts at a
+    /// leading space, and every CJK character is a word by itself. Punctuation is
+    /// attached to the preceding word.
+    pub fn split_words(&self, ids: &[i64]) -> Vec<WordTokens> {
+        let mut words: Vec<WordTokens> = Vec::new();
+        let mut pending = Vec::new();
+        let mut unit_start = 0;
+
+        for (i, id) in ids.iter().enumerate() {
+            let Some(bytes) = self.id_to_bytes.get(id) else {
+                continue;
+            };
+            if pending.is_empty() {
+                unit_start = i;
+            }
+            pending.extend_from_slice(bytes);
+
+            let text = match std::str::from_utf8(&pending) {
+                Ok(text) => text.to_string(),
+                Err(e) if e.error_len().is_some() => String::from_utf8_lossy(&pending).into_owned(),
+                // Incomplete character, wait for the next token
+                Err(_) => continue,
+            };
+            pending.clear();
+
+            let tokens = unit_start..i + 1;
+            push_unit(&mut words, &text, tokens);
+        }
+
+        if !pending.is_empty() {
+            let text = String::from_utf8_lossy(&pending).into_owned();
+            push_unit(&mut words, &text, unit_start..ids.len());
+        }
+
+        words
+    }
+}
+
+fn push_unit(words: &mut Vec<WordTokens>, text: &str, tokens: Range<usize>) {
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if is_cjk(c) {
+            chars.next();
+            words.push(WordTokens { text: c.to_string(), tokens: tokens.clone() });
+            continue;
+        }
+
+        // Take everything up to the next CJK character
+        let mut piece = String::new();
+        while let Some(&c) = chars.peek() {
+            if is_cjk(c) {
+                break;
+            }
+            piece.push(c);
+            chars.next();
+        }
+
+        let attach = match words.last() {
+            None => false,
+            Some(_) if piece.chars().all(is_punctuation) => true,
+            Some(last) => {
+                !piece.starts_with(char::is_whitespace)
+                    && !last.text.chars().any(is_cjk)
+                    && !last.text.chars().all(is_punctuation)
+            }
+        };
+        if attach {
+            let last = words.last_mut().unwrap();
+            last.text.push_str(&piece);
+            last.tokens.end = tokens.end;
+        } else {
+            words.push(WordTokens { text: piece, tokens: tokens.clone() });
+        }
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'   // Hiragana, Katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+        | '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+        | '\u{20000}'..='\u{2FA1F}')
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(c, '，' | '。' | '、' | '！' | '？' | '：' | '；' | '…' | '」' | '』' | '）' | '》')
 }
 
 fn decode_token_bytes(input: &str) -> Vec<u8> {
@@ -77,7 +266,7 @@ fn decode_token_bytes(input: &str) -> Vec<u8> {
 
     // 3. Manual Padding fix
     let mut padded = input.to_string();
-    while padded.len() % 4 != 0 {
+    while !padded.len().is_multiple_of(4) {
         padded.push('=');
     }
     if let Ok(bytes) = general_purpose::STANDARD.decode(&padded) {
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::io::Write;
+use flate2::{Compression, write::ZlibEncoder};
+
+use crate::tokenizer::WordTokens;
+
+/// Seconds per timestamp token step.
+pub const TIME_PRECISION: f32 = 0.02;
+
+/// The result of transcribing a file or a stream segment.
+/// `Display` writes the plain text.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transcript {
+    pub segments: Vec<Segment>,
+}
+
+impl Transcript {
+    pub fn new(segments: Vec<Segment>) -> Self {
+        Self { segments }
+    }
+
+    /// Whether there is no text at all, e.g. for silence.
+    pub fn is_empty(&self) -> bool {
+        self.segments.iter().all(|s| s.text.trim().is_empty())
+    }
+
+    /// Language of the first segment that has one.
+    pub fn language(&self) -> Option<&str> {
+        self.segments.iter().find_map(|s| s.language.as_deref())
+    }
+
+    /// Move every segment and word `seconds` later, for audio cut from a longer recording.
+    pub fn shift(&mut self, seconds: f32) {
+        for segment in &mut self.segments {
+            segment.start += seconds;
+            segment.end += seconds;
+            for word in &mut segment.words {
+                word.start += seconds;
+                word.end += seconds;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            f.write_str(&segment.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// A piece of transcribed text and where it is in the audio.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Segment {
+    /// Start time in seconds.
+    pub start: f32,
+    /// End time in seconds.
+    pub end: f32,
+    pub text: String,
+    /// Text token ids the segment was decoded from.
+    pub tokens: Vec<i64>,
+    /// Language code the model transcribed this segment in, e.g. `zh`.
+    pub language: Option<String>,
+    /// Probability that the window this segment was decoded from has no speech.
+    pub no_speech_prob: f32,
+    /// Geometric mean of the text token probabilities, see `confidence`.
+    pub confidence: f32,
+    /// Word timings, filled when `DecodingOptions::word_timestamps` is set.
+    pub words: Vec<Word>,
+}
+
+/// A word (a single character for Chinese) and where it is in the audio.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Word {
+    /// Start time in seconds.
+    pub start: f32,
+    /// End time in seconds.
+    pub end: f32,
+    pub text: String,
+    /// Geometric mean of the probabilities of the word's tokens.
+    pub confidence: f32,
+}
+
+/// A conversation recorded with one speaker per channel, as time-ordered turns.
+/// `Display` writes one `[start - end] speaker: text` line per turn.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dialogue {
+    pub utterances: Vec<Utterance>,
+}
+
+/// One segment said by one speaker.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Utterance {
+    /// Index of the channel it was recorded on.
+    pub channel: usize,
+    pub speaker: String,
+    /// Start time in seconds.
+    pub start: f32,
+    /// End time in seconds.
+    pub end: f32,
+    pub text: String,
+    pub confidence: f32,
+}
+
+impl Dialogue {
+    /// Merge the transcripts of each channel, ordered by start time.
+    /// Channels without a name in `speakers` are called `Channel 1`, `Channel 2`, ...
+    pub fn from_channels(transcripts: &[Transcript], speakers: &[&str]) -> Self {
+        let mut utterances: Vec<Utterance> = transcripts
+            .iter()
+            .enumerate()
+            .flat_map(|(channel, transcript)| {
+                let speaker = speakers
+                    .get(channel)
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| format!("Channel {}", channel + 1));
+                transcript
+                    .segments
+                    .iter()
+                    .filter(|segment| !segment.text.trim().is_empty())
+                    .map(move |segment| Utterance {
+                        channel,
+                        speaker: speaker.clone(),
+                        start: segment.start,
+                        end: segment.end,
+                        text: segment.text.trim().to_string(),
+                        confidence: segment.confidence,
+                    })
+            })
+            .collect();
+        // Stable, so turns starting together keep the channel order
+        utterances.sort_by(|a, b| a.start.total_cmp(&b.start));
+        Self { utterances }
+    }
+
+    /// The dialogue as a JSON array of utterances.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.utterances).expect("utterances are plain data")
+    }
+}
+
+impl fmt::Display for Dialogue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = |seconds: f32| {
+            let centis = (seconds.max(0.0) * 100.0).round() as u64;
+            format!("{:02}:{:02}.{:02}", centis / 6000, centis / 100 % 60, centis % 100)
+        };
+        for u in &self.utterances {
+            writeln!(f, "[{} - {}] {}: {}", time(u.start), time(u.end), u.speaker, u.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tokens of one window split at timestamp pairs.
+#[derive(Debug, PartialEq)]
+pub struct TimedTokens<'a> {
+    /// Start time in seconds, relative to the window.
+    pub start: f32,
+    /// End time in seconds, relative to the window.
+    pub end: f32,
+    pub tokens: &'a [i64],
+}
+
+/// Split the sampled tokens of a window (everything after the task token) into
+/// segments, following Whisper's long-form rules. Ids from `timestamp_begin` on are
+/// timestamps.
+///
+/// Returns the segments and how many seconds of the window they cover. When the
+/// last segment is left open (no closing timestamp), the window is only consumed
+/// up to the last complete segment, so the caller can seek there and decode the
+/// rest again in the next window.
+pub fn split_at_timestamps(tokens: &[i64], timestamp_begin: i64, window_duration: f32) -> (Vec<TimedTokens<'_>>, f32) {
+    let is_ts = |t: i64| t >= timestamp_begin;
+    let to_secs = |t: i64| (t - timestamp_begin) as f32 * TIME_PRECISION;
+
+    let single_timestamp_ending = tokens.len() >= 2
+        && !is_ts(tokens[tokens.len() - 2])
+        && is_ts(tokens[tokens.len() - 1]);
+
+    let mut slices: Vec<usize> = (1..tokens.len())
+        .filter(|&i| is_ts(tokens[i - 1]) && is_ts(tokens[i]))
+        .collect();
+
+    if slices.is_empty() {
+        // No complete pair: the whole window is one segment
+        let end = match tokens.iter().rev().find(|&&t| is_ts(t)) {
+            Some(&t) if t != timestamp_begin => to_secs(t),
+            _ => window_duration,
+        };
+        let segment = TimedTokens { start: 0.0, end, tokens };
+        return (vec![segment], window_duration);
+    }
+
+    if single_timestamp_ending {
+        slices.push(tokens.len());
+    }
+
+    let mut segments = Vec::with_capacity(slices.len());
+    let mut last_slice = 0;
+    for &current_slice in &slices {
+        let sliced = &tokens[last_slice..current_slice];
+        segments.push(TimedTokens {
+            start: to_secs(sliced[0]),
+            end: to_secs(sliced[sliced.len() - 1]),
+            tokens: sliced,
+        });
+        last_slice = current_slice;
+    }
+
+    let consumed = if single_timestamp_ending {
+        window_duration
+    } else {
+        to_secs(tokens[last_slice - 1])
+    };
+
+    (segments, consumed)
+}
+
+/// Give every word of a segment a start and end time, and a confidence from the
+/// `logprobs` of the segment's text tokens.
+///
+/// `token_times` are the boundaries of the segment's tokens from cross-attention
+/// alignment (token `k` spans `token_times[k]..token_times[k + 1]`); words sharing
+/// tokens split their span evenly. Without alignment, the segment duration is spread
+/// over the words in proportion to their length in characters, which is only a rough
+/// estimate.
+pub fn time_words(
+    words: &[WordTokens],
+    logprobs: &[f32],
+    token_times: Option<&[f32]>,
+    start: f32,
+    end: f32,
+) -> Vec<Word> {
+    let mut timed = Vec::with_capacity(words.len());
+    let word_confidence = |w: &WordTokens| confidence(logprobs.get(w.tokens.clone()).unwrap_or_default());
+
+    match token_times {
+        Some(times) => {
+            let time_at = |i: usize| times[i.min(times.len() - 1)];
+            let mut i = 0;
+            while i < words.len() {
+                let range = &words[i].tokens;
+                let group = words[i..].iter().take_while(|w| w.tokens == *range).count();
+                let (t0, t1) = (time_at(range.start), time_at(range.end));
+                let step = (t1 - t0) / group as f32;
+                for (k, word) in words[i..i + group].iter().enumerate() {
+                    timed.push(Word {
+                        start: t0 + step * k as f32,
+                        end: t0 + step * (k + 1) as f32,
+                        text: word.text.clone(),
+                        confidence: word_confidence(word),
+                    });
+                }
+                i += group;
+            }
+        }
+        None => {
+            let weight = |w: &WordTokens| w.text.trim().chars().count().max(1) as f32;
+            let total: f32 = words.iter().map(weight).sum();
+            let mut cursor = start;
+            for word in words {
+                let duration = (end - start) * weight(word) / total;
+                timed.push(Word {
+                    start: cursor,
+                    end: cursor + duration,
+                    text: word.text.clone(),
+                    confidence: word_confidence(word),
+                });
+                cursor += duration;
+            }
+        }
+    }
+
+    timed
+}
+
+/// Geometric mean probability of tokens from their log probabilities, in `[0, 1]`.
+/// Returns 0 when there are no tokens.
+pub fn confidence(logprobs: &[f32]) -> f32 {
+    if logprobs.is_empty() {
+        return 0.0;
+    }
+    (logprobs.iter().sum::<f32>() / logprobs.len() as f32).exp()
+}
+
+/// Ratio between the size of `text` and its zlib-compressed size.
+/// Repetition loops compress very well, so a high ratio flags them.
+pub fn compression_ratio(text: &str) -> f32 {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec cannot fail
+    let _ = encoder.write_all(text.as_bytes());
+    let compressed = encoder.finish().unwrap_or_default();
+    text.len() as f32 / compressed.len().max(1) as f32
+}
@@ -59,16 +59,19 @@ pub struct VadProcessor {
     speech_chunks: u32,
     waiting_dropped_chunks: u32,
     notified_silence: bool,
+    /// Samples processed so far.
+    position: usize,
+    segment_start: usize,
 }
 
 #[cfg(feature = "stream")]
 impl VadProcessor {
-    pub fn new(config: VadConfig) -> anyhow::Result<Self> {
+    pub fn new(config: VadConfig) -> crate::Result<Self> {
         let vad = VoiceActivityDetector::builder()
             .sample_rate(config.sample_rate)
             .chunk_size(CHUNK_SIZE)
             .build()
-            .map_err(|e| anyhow::anyhow!(e))?;
+            .map_err(|e| crate::BreezeError::Stream(e.to_string()))?;
         Ok(Self {
             vad,
             config,
@@ -79,6 +82,8 @@ impl VadProcessor {
             speech_chunks: 0,
             waiting_dropped_chunks: 0,
             notified_silence: false,
+            position: 0,
+            segment_start: 0,
         })
     }
 
@@ -89,7 +94,14 @@ impl VadProcessor {
         }
     }
 
+    /// Sample index, counted from the first processed chunk, where the segment being
+    /// recorded starts, or else where the last returned segment started.
+    pub fn segment_start(&self) -> usize {
+        self.segment_start
+    }
+
     pub fn process_chunk(&mut self, chunk: &[i16; CHUNK_SIZE]) -> Option<VadOutput> {
+        self.position += CHUNK_SIZE;
         let chunk_duration_ms = (CHUNK_SIZE as f32 / self.config.sample_rate as f32) * 1000.0;
         let probability = chunk
             .iter()
@@ -112,6 +124,7 @@ impl VadProcessor {
                 if probability > self.config.speech_threshold {
                     self.state = VadState::Recording;
                     self.current_segment.extend(self.history_buffer.iter());
+                    self.segment_start = self.position - self.current_segment.len();
                     self.history_buffer.clear();
                     self.silence_chunks = 0;
                     self.speech_chunks = 0;
@@ -211,3 +224,29 @@ impl VadProcessor {
         }
     }
 }
+
+/// Sample ranges of the speech segments in 16kHz `samples`, found as in streaming.
+#[cfg(feature = "stream")]
+pub fn speech_ranges(config: VadConfig, samples: &[f32]) -> crate::Result<Vec<std::ops::Range<usize>>> {
+    let mut vad = VadProcessor::new(config)?;
+    let mut ranges = Vec::new();
+    let mut push = |vad: &VadProcessor, output| {
+        if let Some(VadOutput::Segment(segment)) = output {
+            let start = vad.segment_start();
+            ranges.push(start..(start + segment.len()).min(samples.len()));
+        }
+    };
+
+    for chunk in samples.chunks(CHUNK_SIZE) {
+        // The last chunk is padded with silence
+        let mut pcm = [0i16; CHUNK_SIZE];
+        for (pcm, &x) in pcm.iter_mut().zip(chunk) {
+            *pcm = (x.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        }
+        let output = vad.process_chunk(&pcm);
+        push(&vad, output);
+    }
+    let output = vad.finish();
+    push(&vad, output);
+    Ok(ranges)
+}
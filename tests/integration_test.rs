@@ -1,5 +1,36 @@
-use breeze_asr_rs::BreezeASR;
-use std::path::Path;
+use breeze_asr_rs::tokenizer::Tokenizer;
+use breeze_asr_rs::Segment;
+
+/// A Chinese segment without tokens or words.
+fn segment(start: f32, end: f32, text: &str) -> Segment {
+    Segment {
+        start,
+        end,
+        text: text.to_string(),
+        tokens: Vec::new(),
+        language: Some("zh".to_string()),
+        no_speech_prob: 0.0,
+        confidence: 1.0,
+        words: Vec::new(),
+    }
+}
+
+/// Load a tokenizer whose token `i` is `pieces[i]`, through a tokens file `name`
+/// written to the working directory and removed again.
+fn tokenizer_from(pieces: &[&[u8]], name: &str) -> Tokenizer {
+    use base64::{engine::general_purpose, Engine as _};
+    use std::io::Write;
+
+    let mut file = std::fs::File::create(name).unwrap();
+    for (i, piece) in pieces.iter().enumerate() {
+        writeln!(file, "{} {}", general_purpose::STANDARD.encode(piece), i).unwrap();
+    }
+    drop(file);
+
+    let tokenizer = Tokenizer::new(name).unwrap();
+    std::fs::remove_file(name).unwrap();
+    tokenizer
+}
 
 #[test]
 fn test_audio_preprocessing() {
@@ -29,3 +60,629 @@ fn test_tokenizer_loading() {
     
     std::fs::remove_file("test_tokens.txt").unwrap();
 }
+
+#[test]
+fn test_long_form_windows() {
+    use breeze_asr_rs::audio::{next_window_end, N_SAMPLES, SAMPLE_RATE};
+
+    // Short audio fits in a single window
+    let short = vec![0.1f32; 10 * SAMPLE_RATE];
+    assert_eq!(next_window_end(&short, 0), short.len());
+
+    // 70s of "speech" with a silent gap at 27s: the first window is cut inside the gap
+    let mut long = vec![0.1f32; 70 * SAMPLE_RATE];
+    let gap = 27 * SAMPLE_RATE;
+    for x in &mut long[gap..gap + SAMPLE_RATE / 10] {
+        *x = 0.0;
+    }
+    let end = next_window_end(&long, 0);
+    assert!(end >= gap && end < gap + SAMPLE_RATE / 10);
+
+    // Windows never exceed 30s and always make progress
+    let mut seek = 0;
+    while seek < long.len() {
+        let end = next_window_end(&long, seek);
+        assert!(end > seek && end - seek <= N_SAMPLES);
+        seek = end;
+    }
+}
+
+#[test]
+fn test_split_at_timestamps() {
+    use breeze_asr_rs::transcript::split_at_timestamps;
+
+    const TS: i64 = 50364;
+    // <|0.00|> a b <|1.00|><|1.00|> c <|2.50|><|2.50|> d
+    let tokens = [TS, 1, 2, TS + 50, TS + 50, 3, TS + 125, TS + 125, 4];
+    let (segments, consumed) = split_at_timestamps(&tokens, TS, 30.0);
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].tokens, &[TS, 1, 2, TS + 50]);
+    assert!((segments[0].end - 1.0).abs() < 1e-6);
+    assert!((segments[1].start - 1.0).abs() < 1e-6);
+    assert!((segments[1].end - 2.5).abs() < 1e-6);
+    // The open last segment is decoded again in the next window
+    assert!((consumed - 2.5).abs() < 1e-6);
+
+    // Ending on a single timestamp consumes the whole window
+    let tokens = [TS, 1, TS + 50, TS + 50, 2, TS + 100];
+    let (segments, consumed) = split_at_timestamps(&tokens, TS, 30.0);
+    assert_eq!(segments.len(), 2);
+    assert!((segments[1].end - 2.0).abs() < 1e-6);
+    assert_eq!(consumed, 30.0);
+
+    // No timestamps at all: one segment spanning the window
+    let (segments, consumed) = split_at_timestamps(&[1, 2, 3], TS, 12.0);
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].end, 12.0);
+    assert_eq!(consumed, 12.0);
+}
+
+#[test]
+fn test_word_splitting_and_timing() {
+    use breeze_asr_rs::transcript::time_words;
+
+    let pieces: [&[u8]; 6] = [b" hello", b" wor", b"ld", b"\xe4\xb8", b"\xad", "文，".as_bytes()];
+    let tokenizer = tokenizer_from(&pieces, "test_words_tokens.txt");
+
+    let words = tokenizer.split_words(&[0, 1, 2, 3, 4, 5]);
+    let texts: Vec<&str> = words.iter().map(|w| w.text.as_str()).collect();
+    assert_eq!(texts, [" hello", " world", "中", "文，"]);
+    assert_eq!(words[1].tokens, 1..3);
+    assert_eq!(words[2].tokens, 3..5);
+
+    // Without alignment the segment is spread by character count
+    let logprobs = [-0.1, -0.2, -0.4, -1.0, -1.0, -0.5];
+    let timed = time_words(&words, &logprobs, None, 0.0, 13.0);
+    assert_eq!(timed.len(), 4);
+    assert!((timed[0].end - 5.0).abs() < 1e-4);
+    assert!((timed[3].end - 13.0).abs() < 1e-4);
+
+    // With token boundaries, words take the span of their tokens
+    let times = [0.0, 1.0, 1.5, 2.0, 2.5, 3.0, 4.0];
+    let timed = time_words(&words, &logprobs, Some(&times), 0.0, 4.0);
+    assert_eq!((timed[1].start, timed[1].end), (1.0, 2.0));
+    assert_eq!((timed[2].start, timed[2].end), (2.0, 3.0));
+
+    // Confidence is the geometric mean probability of the word's tokens
+    assert!((timed[0].confidence - (-0.1f32).exp()).abs() < 1e-6);
+    assert!((timed[1].confidence - (-0.3f32).exp()).abs() < 1e-6);
+    assert!((timed[2].confidence - (-1.0f32).exp()).abs() < 1e-6);
+}
+
+#[test]
+fn test_dtw_and_median_filter() {
+    use breeze_asr_rs::alignment::{dtw, median_filter};
+    use ndarray::array;
+
+    let cost = array![[0.0, 0.0, 9.0, 9.0], [9.0, 9.0, 0.0, 9.0], [9.0, 9.0, 9.0, 0.0]];
+    assert_eq!(dtw(&cost), [(0, 0), (0, 1), (1, 2), (2, 3)]);
+    // Several tokens can share a frame
+    let cost = array![[0.0, 9.0], [0.0, 9.0], [9.0, 0.0]];
+    assert_eq!(dtw(&cost), [(0, 0), (1, 0), (2, 1)]);
+
+    // Reflected, the ends see their neighbour twice; repeating the edge would keep the 5
+    assert_eq!(median_filter(&[5.0, 1.0, 1.0, 1.0, 5.0], 3), [1.0, 1.0, 1.0, 1.0, 1.0]);
+    assert_eq!(median_filter(&[1.0, 1.0, 9.0, 1.0, 1.0], 3), [1.0; 5]);
+    assert_eq!(median_filter(&[3.0, 2.0, 1.0, 0.0], 5), [2.0, 2.0, 1.0, 1.0]);
+    // Too short to filter
+    assert_eq!(median_filter(&[4.0, 2.0], 7), [4.0, 2.0]);
+}
+
+#[test]
+fn test_token_boundaries() {
+    use breeze_asr_rs::alignment::token_boundaries;
+    use ndarray::Array3;
+
+    // Two heads where position k attends to frames 10k..10k+10
+    let qk = Array3::from_shape_fn((2, 3, 40), |(_, position, frame)| {
+        if frame / 10 == position { 10.0 } else { 0.0 }
+    });
+    // One time per position, at the first frame each one attends to
+    let times = token_boundaries(&qk, 30);
+    let frames: Vec<i32> = times.iter().map(|t| (t / 0.02).round() as i32).collect();
+    assert_eq!(frames, [0, 10, 20]);
+}
+
+#[test]
+fn test_tokenizer_encode() {
+    let tokenizer = tokenizer_from(&["a", "b", "ab", "abc", " "].map(str::as_bytes), "test_encode_tokens.txt");
+
+    let ids = tokenizer.encode("abcab a");
+    assert_eq!(ids, [3, 2, 4, 0]);
+    assert_eq!(tokenizer.decode(&ids), "abcab a");
+}
+
+#[test]
+fn test_tokenizer_bpe_ranks() {
+    let pieces = ["a", "b", "c", "bc", "ab", " ", " b"].map(str::as_bytes);
+    let tokenizer = tokenizer_from(&pieces, "test_bpe_tokens.txt");
+
+    // "bc" has a lower rank than "ab", so it is merged first
+    assert_eq!(tokenizer.encode("abc"), [0, 3]);
+    // The last space of a run belongs to the following word
+    assert_eq!(tokenizer.encode("a  b"), [0, 5, 6]);
+}
+
+#[test]
+fn test_compression_ratio() {
+    use breeze_asr_rs::transcript::compression_ratio;
+
+    let looping = "謝謝".repeat(100);
+    assert!(compression_ratio(&looping) > 2.4);
+    assert!(compression_ratio("今天天氣很好，我們去公園散步吧。") < 2.4);
+}
+
+#[test]
+fn test_compression_ratio_ignores_prompt() {
+    use breeze_asr_rs::model::{DecodingResult, SpecialTokens};
+    use breeze_asr_rs::transcript::compression_ratio;
+
+    let pieces = ["謝謝", "今天天氣很好，", "我們去公園散步吧。"].map(str::as_bytes);
+    let tokenizer = tokenizer_from(&pieces, "test_prompt_tokens.txt");
+
+    let special = SpecialTokens::default();
+    let zh = special.language_token("zh").unwrap();
+    let mut tokens = vec![special.sot_prev];
+    tokens.extend([0; 100]);
+    tokens.extend([special.sot, zh, special.transcribe, special.timestamp_begin, 1, 2]);
+    tokens.extend([special.timestamp_begin + 100, special.eot]);
+    let result = DecodingResult {
+        token_logprobs: vec![0.0; tokens.len()],
+        tokens,
+        avg_logprob: -0.2,
+        temperature: 0.0,
+        no_speech_prob: 0.0,
+    };
+
+    // The looping prompt alone would trigger a fallback
+    assert!(compression_ratio(&tokenizer.decode(&result.tokens)) > 2.4);
+    let text_tokens = result.text_tokens(&special);
+    assert_eq!(text_tokens, [1, 2]);
+    assert!(compression_ratio(&tokenizer.decode(&text_tokens)) < 2.4);
+}
+
+#[test]
+fn test_silence_detection() {
+    use breeze_asr_rs::model::DecodingResult;
+    use breeze_asr_rs::DecodingOptions;
+
+    let options = DecodingOptions::default();
+    let result = |no_speech_prob, avg_logprob| DecodingResult {
+        tokens: Vec::new(),
+        token_logprobs: Vec::new(),
+        avg_logprob,
+        temperature: 0.0,
+        no_speech_prob,
+    };
+
+    assert!(result(0.9, -1.5).is_silence(&options));
+    // Confident text is kept even if the model also thinks there may be no speech
+    assert!(!result(0.9, -0.3).is_silence(&options));
+    assert!(!result(0.1, -1.5).is_silence(&options));
+
+    let disabled = DecodingOptions { no_speech_threshold: None, ..options.clone() };
+    assert!(!result(0.9, -1.5).is_silence(&disabled));
+    let no_logprob = DecodingOptions { logprob_threshold: None, ..options };
+    assert!(result(0.9, -0.3).is_silence(&no_logprob));
+}
+
+#[test]
+fn test_transcript_display() {
+    use breeze_asr_rs::Transcript;
+
+    let transcript = Transcript::new(vec![segment(0.0, 1.5, "你好，"), segment(1.5, 3.0, "世界。")]);
+    assert_eq!(transcript.to_string(), "你好，世界。");
+    assert_eq!(transcript.language(), Some("zh"));
+    assert!(!transcript.is_empty());
+    assert!(Transcript::new(vec![segment(0.0, 1.0, " ")]).is_empty());
+}
+
+#[test]
+fn test_special_tokens_from_vocab_size() {
+    use breeze_asr_rs::model::SpecialTokens;
+
+    // large-v2, the Breeze ASR 25 base
+    let v2 = SpecialTokens::from_vocab_size(51865);
+    assert_eq!((v2.eot, v2.sot, v2.first_language), (50257, 50258, 50259));
+    assert_eq!((v2.translate, v2.transcribe, v2.sot_prev), (50358, 50359, 50361));
+    assert_eq!((v2.no_speech, v2.no_timestamps, v2.timestamp_begin), (50362, 50363, 50364));
+    assert_eq!(v2.languages().len(), 99);
+    assert!(v2.multilingual);
+    assert_eq!(v2.language_token("zh").unwrap(), 50260);
+
+    // large-v3 added Cantonese, shifting everything after the languages
+    let v3 = SpecialTokens::from_vocab_size(51866);
+    assert_eq!(v3.languages().last(), Some(&"yue"));
+    assert_eq!((v3.transcribe, v3.no_timestamps, v3.timestamp_begin), (50360, 50364, 50365));
+
+    // English-only models
+    let en = SpecialTokens::from_vocab_size(51864);
+    assert!(!en.multilingual);
+    assert_eq!((en.eot, en.sot, en.timestamp_begin), (50256, 50257, 50363));
+}
+
+#[test]
+fn test_sot_sequence() {
+    use breeze_asr_rs::model::SpecialTokens;
+    use breeze_asr_rs::{DecodingOptions, Task};
+
+    let special = SpecialTokens::default();
+    let zh = special.language_token("zh").unwrap();
+    let transcribe = DecodingOptions { timestamps: true, ..DecodingOptions::default() };
+    assert_eq!(special.sot_sequence(zh, &transcribe, &[], 223), [50258, 50260, 50359]);
+
+    let translate = DecodingOptions { task: Task::Translate, timestamps: false, ..DecodingOptions::default() };
+    assert_eq!(special.sot_sequence(zh, &translate, &[], 223), [50258, 50260, 50358, 50363]);
+    let en = special.language_token("en").unwrap();
+    assert_eq!(special.sot_sequence(en, &translate, &[], 223), [50258, 50259, 50358, 50363]);
+
+    // English-only models have no language or task token
+    let english_only = SpecialTokens::from_vocab_size(51864);
+    assert_eq!(english_only.sot_sequence(en, &transcribe, &[], 223), [50257]);
+    assert_eq!(english_only.sot_sequence(en, &translate, &[], 223), [50257, 50362]);
+}
+
+#[test]
+fn test_sot_sequence_prompt() {
+    use breeze_asr_rs::model::{ModelDims, SpecialTokens};
+    use breeze_asr_rs::DecodingOptions;
+
+    let special = SpecialTokens::default();
+    let zh = special.language_token("zh").unwrap();
+    let options = DecodingOptions { timestamps: true, ..DecodingOptions::default() };
+    let max_prompt_len = ModelDims::default().max_prompt_len();
+    assert_eq!(max_prompt_len, 223);
+
+    let sequence = special.sot_sequence(zh, &options, &[1, 2, 3], max_prompt_len);
+    assert_eq!(sequence, [50361, 1, 2, 3, 50258, 50260, 50359]);
+
+    // Only the most recent tokens of a long prompt are kept
+    let prompt: Vec<i64> = (0..300).collect();
+    let sequence = special.sot_sequence(zh, &options, &prompt, max_prompt_len);
+    assert_eq!(sequence.len(), 1 + 223 + 3);
+    assert_eq!(sequence[0], 50361);
+    assert_eq!(sequence[1..224], prompt[77..]);
+    assert_eq!(sequence[224..], [50258, 50260, 50359]);
+}
+
+#[test]
+fn test_timestamp_rules() {
+    use breeze_asr_rs::model::{SpecialTokens, MAX_INITIAL_TIMESTAMP_INDEX};
+
+    // A tiny vocabulary: text tokens 0..10, then the special tokens and 61 timestamps
+    let special = SpecialTokens {
+        eot: 10,
+        sot: 11,
+        first_language: 12,
+        n_languages: 2,
+        translate: 14,
+        transcribe: 15,
+        sot_prev: 16,
+        no_speech: 17,
+        no_timestamps: 18,
+        timestamp_begin: 19,
+        multilingual: true,
+    };
+    let ts = |index: i64| special.timestamp_begin + index;
+    let rules = |sampled: &[i64]| {
+        let mut tokens = vec![special.sot, special.first_language, special.transcribe];
+        tokens.extend_from_slice(sampled);
+        // Text token 5 and EOT are likely enough to survive the timestamp preference
+        let mut logits = vec![0.0f32; ts(60) as usize + 1];
+        logits[5] = 10.0;
+        logits[special.eot as usize] = 10.0;
+        special.apply_timestamp_rules(&mut logits, &tokens);
+        logits
+    };
+    let allowed = |logits: &[f32], token: i64| logits[token as usize].is_finite();
+
+    // The first token is a timestamp of at most 1s
+    let first = rules(&[]);
+    assert!(!allowed(&first, 5) && !allowed(&first, special.eot));
+    assert!(allowed(&first, ts(0)) && allowed(&first, ts(MAX_INITIAL_TIMESTAMP_INDEX)));
+    assert!(!allowed(&first, ts(MAX_INITIAL_TIMESTAMP_INDEX + 1)));
+    assert!(!allowed(&first, special.no_timestamps));
+
+    // A timestamp after text closes the segment: another timestamp or EOT, no text
+    let closing = rules(&[ts(0), 5, ts(3)]);
+    assert!(!allowed(&closing, 5));
+    assert!(allowed(&closing, special.eot) && allowed(&closing, ts(3)) && allowed(&closing, ts(10)));
+    assert!(!allowed(&closing, ts(2)));
+
+    // A pair of timestamps is followed by text
+    let paired = rules(&[ts(0), 5, ts(3), ts(3)]);
+    assert!(allowed(&paired, 5) && allowed(&paired, special.eot));
+    assert!((0..=60).all(|i| !allowed(&paired, ts(i))));
+
+    // Timestamps never decrease, and a segment has a non-zero length
+    let open = rules(&[ts(4), 5]);
+    assert!(allowed(&open, 5));
+    assert!((0..=4).all(|i| !allowed(&open, ts(i))));
+    assert!(allowed(&open, ts(5)));
+
+    for logits in [first, closing, paired, open] {
+        assert!(!allowed(&logits, special.no_timestamps));
+    }
+}
+
+#[test]
+fn test_beam_search_selection() {
+    use breeze_asr_rs::model::{max_candidates, select_candidates, sequence_score, BeamCandidate};
+
+    assert_eq!(max_candidates(5, None), 5);
+    assert_eq!(max_candidates(5, Some(1.5)), 8);
+    assert_eq!(max_candidates(4, Some(0.5)), 2);
+    assert_eq!(max_candidates(1, Some(0.1)), 1);
+
+    let eot = 10;
+    let candidate = |parent, token, sum_logprob| BeamCandidate { parent, token, logprob: sum_logprob, sum_logprob };
+    let candidates = vec![
+        candidate(0, 1, -0.5),
+        candidate(0, eot, -0.1),
+        candidate(1, eot, -0.2),
+        candidate(1, 2, -0.3),
+        candidate(0, 3, -0.9),
+        candidate(1, eot, -0.6),
+    ];
+
+    // Best first; EOT candidates don't take a beam, and only fill the room left
+    let (selected, finished) = select_candidates(candidates.clone(), eot, 2, 1);
+    assert_eq!(selected, [candidate(1, 2, -0.3), candidate(0, 1, -0.5)]);
+    assert_eq!(finished, [candidate(0, eot, -0.1)]);
+    // Candidates after the beams are full are not looked at
+    let (_, finished) = select_candidates(candidates, eot, 2, 5);
+    assert_eq!(finished, [candidate(0, eot, -0.1), candidate(1, eot, -0.2)]);
+
+    // A short and a long sequence: averaging per token favours the long one,
+    // while a length penalty of 0 ranks by total log probability
+    let (short, long) = ((-1.0, 2), (-3.0, 10));
+    let best = |penalty| {
+        if sequence_score(short.0, short.1, penalty) > sequence_score(long.0, long.1, penalty) { "short" } else { "long" }
+    };
+    assert_eq!(best(None), "long");
+    assert_eq!(best(Some(0.0)), "short");
+    assert_eq!(sequence_score(-3.0, 7, Some(1.0)), -1.5);
+}
+
+#[test]
+fn test_audio_config_mel_bins() {
+    use breeze_asr_rs::audio::{AudioConfig, AudioProcessor, SAMPLE_RATE};
+
+    let samples = vec![0.1f32; 2 * SAMPLE_RATE];
+
+    let default = AudioProcessor::new().unwrap();
+    assert_eq!(default.process_pcm(&samples).dim(), (80, 3000));
+
+    let v3 = AudioProcessor::with_config(AudioConfig { n_mels: 128, ..AudioConfig::default() }).unwrap();
+    assert_eq!(v3.process_pcm(&samples).dim(), (128, 3000));
+
+    assert!(AudioProcessor::with_config(AudioConfig { n_mels: 0, ..AudioConfig::default() }).is_err());
+    assert!(AudioProcessor::with_config(AudioConfig { fmax: 9000.0, ..AudioConfig::default() }).is_err());
+}
+
+#[test]
+fn test_decoding_options_validation() {
+    use breeze_asr_rs::DecodingOptions;
+
+    assert!(DecodingOptions::default().validate().is_ok());
+    assert!(DecodingOptions { beam_size: Some(0), ..Default::default() }.validate().is_err());
+    assert!(DecodingOptions { temperature: 1.5, ..Default::default() }.validate().is_err());
+    assert!(DecodingOptions { patience: Some(0.0), ..Default::default() }.validate().is_err());
+    assert!(DecodingOptions { temperature_increment: Some(-0.2), ..Default::default() }.validate().is_err());
+}
+
+#[test]
+fn test_error_kinds() {
+    use breeze_asr_rs::audio::AudioProcessor;
+    use breeze_asr_rs::model::SpecialTokens;
+    use breeze_asr_rs::BreezeError;
+    use std::io::Write;
+
+    assert!(matches!(Tokenizer::new("missing_tokens.txt"), Err(BreezeError::ModelNotFound(_))));
+
+    let mut file = std::fs::File::create("test_error_tokens.txt").unwrap();
+    writeln!(file, "aGVsbG8= 0").unwrap();
+    writeln!(file, "d29ybGQ= one").unwrap();
+    let result = Tokenizer::new("test_error_tokens.txt");
+    std::fs::remove_file("test_error_tokens.txt").unwrap();
+    assert!(matches!(result, Err(BreezeError::TokenizerParse { line: 2, .. })));
+
+    std::fs::write("test_error_audio.wav", b"RIFF\x0c\x00\x00\x00WAVEdata\x00\x00\x00\x00").unwrap();
+    let result = AudioProcessor::new().unwrap().load_audio("test_error_audio.wav");
+    std::fs::remove_file("test_error_audio.wav").unwrap();
+    assert!(matches!(result, Err(BreezeError::AudioDecode(_))));
+
+    std::fs::write("test_error_audio.bin", b"not an audio file").unwrap();
+    let result = AudioProcessor::new().unwrap().load_audio("test_error_audio.bin");
+    std::fs::remove_file("test_error_audio.bin").unwrap();
+    assert!(matches!(result, Err(BreezeError::UnsupportedAudioFormat(_))));
+
+    let special = SpecialTokens::default();
+    assert!(matches!(special.language_token("xx"), Err(BreezeError::UnsupportedLanguage(_))));
+}
+
+#[test]
+fn test_session_pool_options() {
+    use breeze_asr_rs::{BreezeASR, BreezeError, SessionOptions};
+
+    fn assert_shareable<T: Send + Sync>() {}
+    assert_shareable::<BreezeASR>();
+
+    assert!(SessionOptions::default().validate().is_ok());
+    let result = BreezeASR::builder()
+        .model_dir("missing_models")
+        .session_options(SessionOptions { pool_size: 0, ..Default::default() })
+        .build();
+    assert!(matches!(result, Err(BreezeError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_builder_batch_size() {
+    use breeze_asr_rs::{BreezeASR, BreezeError};
+
+    let result = BreezeASR::builder().model_dir("missing_models").batch_size(0).build();
+    assert!(matches!(result, Err(BreezeError::InvalidConfig(_))));
+    let result = BreezeASR::builder().model_dir("missing_models").batch_size(4).build();
+    assert!(matches!(result, Err(BreezeError::ModelNotFound(_))));
+}
+
+#[test]
+fn test_wav_sample_formats() {
+    use breeze_asr_rs::audio::{AudioProcessor, SAMPLE_RATE};
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    let processor = AudioProcessor::new().unwrap();
+    let expected = [0.0f32, 0.5, -0.5, -1.0];
+    let spec = |bits_per_sample, sample_format| WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE as u32,
+        bits_per_sample,
+        sample_format,
+    };
+
+    for bits in [8u16, 16, 24, 32] {
+        let path = format!("test_wav_int{}.wav", bits);
+        let scale = (1i64 << (bits - 1)) as f32;
+        let mut writer = WavWriter::create(&path, spec(bits, SampleFormat::Int)).unwrap();
+        for x in expected {
+            let sample = (x * scale) as i32;
+            match bits {
+                8 => writer.write_sample(sample as i8).unwrap(),
+                16 => writer.write_sample(sample as i16).unwrap(),
+                _ => writer.write_sample(sample).unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+
+        let samples = processor.load_audio(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples, expected, "{}-bit int", bits);
+    }
+
+    let path = "test_wav_float32.wav";
+    let mut writer = WavWriter::create(path, spec(32, SampleFormat::Float)).unwrap();
+    for x in expected {
+        writer.write_sample(x).unwrap();
+    }
+    writer.finalize().unwrap();
+    let samples = processor.load_audio(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(samples, expected);
+}
+
+#[test]
+fn test_channel_policy() {
+    use breeze_asr_rs::audio::{AudioProcessor, ChannelPolicy, SAMPLE_RATE};
+    use breeze_asr_rs::BreezeError;
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    let path = "test_stereo.wav";
+    let spec = WavSpec { channels: 2, sample_rate: SAMPLE_RATE as u32, bits_per_sample: 32, sample_format: SampleFormat::Float };
+    let mut writer = WavWriter::create(path, spec).unwrap();
+    for (left, right) in [(0.5f32, -0.25f32), (0.25, 0.75), (-1.0, 0.0)] {
+        writer.write_sample(left).unwrap();
+        writer.write_sample(right).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let processor = AudioProcessor::new().unwrap();
+    let average = processor.load_audio(path).unwrap();
+    let right = processor.load_channels(path, ChannelPolicy::Select(1)).unwrap();
+    let separate = processor.load_channels(path, ChannelPolicy::Separate).unwrap();
+    let missing = processor.load_channels(path, ChannelPolicy::Select(2));
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(average, vec![0.125, 0.5, -0.5]);
+    assert_eq!(right, vec![vec![-0.25, 0.75, 0.0]]);
+    assert_eq!(separate, vec![vec![0.5, 0.25, -1.0], vec![-0.25, 0.75, 0.0]]);
+    assert!(matches!(missing, Err(BreezeError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_dialogue_merge() {
+    use breeze_asr_rs::{Dialogue, Transcript};
+
+    let mut agent = Transcript::new(vec![segment(0.0, 1.5, "您好，請問有什麼需要？"), segment(0.5, 1.0, " ")]);
+    agent.segments.push(segment(65.0, 66.25, "好的，沒問題。"));
+    let mut customer = Transcript::new(vec![segment(0.0, 2.0, "我想查帳單。")]);
+    customer.shift(2.0);
+
+    let dialogue = Dialogue::from_channels(&[agent, customer], &["Agent"]);
+    let speakers: Vec<&str> = dialogue.utterances.iter().map(|u| u.speaker.as_str()).collect();
+    assert_eq!(speakers, ["Agent", "Channel 2", "Agent"]);
+    assert_eq!(dialogue.utterances[1].channel, 1);
+    assert_eq!(
+        dialogue.to_string(),
+        "[00:00.00 - 00:01.50] Agent: 您好，請問有什麼需要？\n\
+         [00:02.00 - 00:04.00] Channel 2: 我想查帳單。\n\
+         [01:05.00 - 01:06.25] Agent: 好的，沒問題。\n"
+    );
+
+    #[cfg(feature = "serde")]
+    assert!(dialogue.to_json().contains("\"speaker\": \"Channel 2\""));
+}
+
+#[test]
+fn test_in_memory_audio() {
+    use breeze_asr_rs::audio::{resample_audio, AudioProcessor, SAMPLE_RATE};
+    use breeze_asr_rs::BreezeError;
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use std::io::Cursor;
+
+    let spec = WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, sample_format: SampleFormat::Int };
+    let mut bytes = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut bytes, spec).unwrap();
+    for i in 0..8000 {
+        writer.write_sample(((i as f32 * 0.05).sin() * 10000.0) as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    bytes.set_position(0);
+
+    let processor = AudioProcessor::new().unwrap();
+    let samples = processor.load_reader(bytes).unwrap();
+    assert_eq!(samples.len(), SAMPLE_RATE);
+    // Upsampled, not shifted by the resampler's delay
+    let expected = (4000.0f32 * 0.05).sin() * 10000.0 / 32768.0;
+    assert!((samples[8000] - expected).abs() < 0.02, "{} != {}", samples[8000], expected);
+
+    let result = processor.load_reader(Cursor::new(b"not an audio file".to_vec()));
+    assert!(matches!(result, Err(BreezeError::UnsupportedAudioFormat(_))));
+
+    assert_eq!(resample_audio(&[0.5, -0.5], SAMPLE_RATE, SAMPLE_RATE).unwrap(), vec![0.5, -0.5]);
+    let downsampled = resample_audio(&vec![0.25; 44100], 44100, SAMPLE_RATE).unwrap();
+    assert_eq!(downsampled.len(), SAMPLE_RATE);
+    assert!((downsampled[8000] - 0.25).abs() < 0.01);
+    assert!(matches!(resample_audio(&[0.5], 0, SAMPLE_RATE), Err(BreezeError::Resample(_))));
+}
+
+#[cfg(feature = "formats")]
+#[test]
+fn test_compressed_formats() {
+    use breeze_asr_rs::audio::{AudioProcessor, SAMPLE_RATE};
+    use std::io::Cursor;
+
+    let processor = AudioProcessor::new().unwrap();
+    let rms = |s: &[f32]| (s.iter().map(|x| x * x).sum::<f32>() / s.len() as f32).sqrt();
+    let sine_rms = 0.5 / 2f32.sqrt();
+
+    // 0.25s of a 440Hz sine at half scale, 44.1kHz
+    let flac = processor.load_audio("tests/fixtures/sine.flac").unwrap();
+    assert_eq!(flac.len(), SAMPLE_RATE / 4);
+    assert!((rms(&flac) - sine_rms).abs() < 0.01, "{}", rms(&flac));
+
+    // 28 silent frames of 1152 samples at 32kHz
+    let mp3 = processor.load_audio("tests/fixtures/silence.mp3").unwrap();
+    assert_eq!(mp3.len(), 28 * 1152 / 2);
+    assert!(mp3.iter().all(|x| x.abs() < 1e-3));
+
+    // The same sine encoded from 48kHz, without the encoder's pre-skip and end padding
+    let opus = processor.load_reader(Cursor::new(std::fs::read("tests/fixtures/sine.opus").unwrap()));
+    #[cfg(feature = "opus")]
+    {
+        let opus = opus.unwrap();
+        assert_eq!(opus.len(), SAMPLE_RATE / 4);
+        assert!((rms(&opus) - sine_rms).abs() < 0.05, "{}", rms(&opus));
+    }
+    #[cfg(not(feature = "opus"))]
+    assert!(matches!(opus, Err(breeze_asr_rs::BreezeError::UnsupportedAudioFormat(_))));
+}